@@ -6,15 +6,22 @@ struct SignedCommit {
     version: u8,
     prev: Option<crate::dagcbor::DagCborCid>,
     data: crate::dagcbor::DagCborCid,
+    rev: Option<String>,
     sig: Vec<u8>,
 }
 
 pub struct Blockstore {
+    rev: Option<String>,
     mst: std::collections::HashMap<Vec<u8>, cid::Cid>,
     blocks: std::collections::HashMap<cid::Cid, Vec<u8>>,
 }
 
 impl Blockstore {
+    /// The revision of the signed commit, if it is a v3 commit.
+    pub fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
     pub fn get_by_cid(&self, cid: &cid::Cid) -> Option<&[u8]> {
         Some(self.blocks.get(cid)?)
     }
@@ -36,6 +43,40 @@ impl Blockstore {
     }
 }
 
+/// The blocks of a CAR file that doesn't necessarily hold a whole repo, such as the blocks a
+/// firehose commit carries. Records can be looked up by CID, but not by key.
+pub struct PartialBlockstore {
+    roots: Vec<cid::Cid>,
+    blocks: std::collections::HashMap<cid::Cid, Vec<u8>>,
+}
+
+impl PartialBlockstore {
+    pub fn roots(&self) -> &[cid::Cid] {
+        &self.roots
+    }
+
+    pub fn get_by_cid(&self, cid: &cid::Cid) -> Option<&[u8]> {
+        Some(self.blocks.get(cid)?)
+    }
+
+    /// Decodes the record with the given CID, or returns `None` if it isn't in the block set.
+    pub fn get_record<T: serde::de::DeserializeOwned>(
+        &self,
+        cid: &cid::Cid,
+    ) -> Result<Option<T>, Error> {
+        let block = if let Some(block) = self.get_by_cid(cid) {
+            block
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(ciborium::from_reader(std::io::Cursor::new(block))?))
+    }
+
+    pub fn cids(&self) -> impl Iterator<Item = &cid::Cid> {
+        self.blocks.keys()
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("ciborium: {0}")]
@@ -81,10 +122,11 @@ impl Loader {
         self
     }
 
-    pub async fn load(
+    /// Reads every block of a CAR file without decoding the MST.
+    pub async fn load_partial(
         &self,
         r: &mut (impl futures_util::AsyncRead + Send + std::marker::Unpin),
-    ) -> Result<Blockstore, Error> {
+    ) -> Result<PartialBlockstore, Error> {
         let mut cr = rs_car::CarReader::new(r, self.validate_block_hash).await?;
 
         let roots = cr.header.roots.clone();
@@ -94,6 +136,15 @@ impl Loader {
             blocks.insert(cid.clone(), block);
         }
 
+        Ok(PartialBlockstore { roots, blocks })
+    }
+
+    pub async fn load(
+        &self,
+        r: &mut (impl futures_util::AsyncRead + Send + std::marker::Unpin),
+    ) -> Result<Blockstore, Error> {
+        let PartialBlockstore { roots, blocks } = self.load_partial(r).await?;
+
         let root_commit = roots.first().ok_or_else(|| Error::NoRoots)?;
         let commit: SignedCommit = ciborium::from_reader(std::io::Cursor::new(
             blocks
@@ -103,6 +154,10 @@ impl Loader {
         let mst = crate::mst::Decoder::new()
             .ignore_missing(self.mst_ignore_missing)
             .decode(&blocks, &commit.data.into())?;
-        Ok(Blockstore { mst, blocks })
+        Ok(Blockstore {
+            rev: commit.rev,
+            mst,
+            blocks,
+        })
     }
 }
@@ -0,0 +1,33 @@
+-- The schema as it was when it was applied by hand from skylight-followsingester/schema.sql.
+-- Everything is created only if it doesn't exist, so that running the migrations against such a
+-- database adopts it and then upgrades it.
+CREATE SCHEMA IF NOT EXISTS follows;
+
+CREATE TABLE IF NOT EXISTS follows.cursor (
+    cursor BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS cursor_single ON follows.cursor ((0));
+
+CREATE SEQUENCE IF NOT EXISTS follows.dids_id_seq
+AS INT -- noqa: PRS
+START -2147483648
+MINVALUE -2147483648
+NO MAXVALUE;
+
+CREATE TABLE IF NOT EXISTS follows.dids (
+    id INT NOT NULL PRIMARY KEY DEFAULT nextval(
+        'follows.dids_id_seq'::REGCLASS
+    ),
+    did TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS follows_dids_idx ON follows.dids (did);
+
+CREATE TABLE IF NOT EXISTS follows.edges (
+    actor_id INT NOT NULL,
+    rkey TEXT NOT NULL,
+    subject_id INT NOT NULL,
+    PRIMARY KEY (actor_id, rkey)
+);
+
+CREATE INDEX IF NOT EXISTS edges_outgoing_idx ON follows.edges (actor_id, subject_id);
+CREATE INDEX IF NOT EXISTS edges_incoming_idx ON follows.edges (subject_id, actor_id);
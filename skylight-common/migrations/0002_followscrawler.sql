@@ -0,0 +1,18 @@
+-- The schema as it was when it was applied by hand from skylight-followscrawler/schema.sql. See
+-- 0001_follows.sql.
+CREATE SCHEMA IF NOT EXISTS followscrawler;
+
+CREATE TABLE IF NOT EXISTS followscrawler.cursor (
+    cursor TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS cursor_single ON followscrawler.cursor ((0));
+
+CREATE TABLE IF NOT EXISTS followscrawler.pending (
+    did TEXT PRIMARY KEY
+);
+
+CREATE TABLE IF NOT EXISTS followscrawler.errors (
+    did TEXT PRIMARY KEY,
+    why TEXT NOT NULL,
+    ts TIMESTAMPTZ DEFAULT NOW()
+);
@@ -0,0 +1,16 @@
+-- The schema as it was when it was applied by hand from skylight-plcingester/schema.sql. See
+-- 0001_follows.sql.
+CREATE SCHEMA IF NOT EXISTS plc;
+
+CREATE TABLE IF NOT EXISTS plc.dids (
+    did TEXT NOT NULL,
+    also_known_as TEXT [] NOT NULL,
+    PRIMARY KEY (did)
+);
+
+CREATE INDEX IF NOT EXISTS dids_also_known_as_idx ON plc.dids USING gin (also_known_as);
+
+CREATE TABLE IF NOT EXISTS plc.cursor (
+    cursor TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS cursor_single ON plc.cursor ((0));
@@ -0,0 +1,22 @@
+-- Edges that existed before this migration get the time it ran as their first and last seen.
+ALTER TABLE follows.edges
+ADD COLUMN created_at TIMESTAMPTZ,
+ADD COLUMN first_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+ADD COLUMN last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE TABLE follows.edges_history (
+    actor_id INT NOT NULL,
+    rkey TEXT NOT NULL,
+    subject_id INT NOT NULL,
+    created_at TIMESTAMPTZ,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    deleted_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX edges_history_outgoing_idx ON follows.edges_history (
+    actor_id, subject_id
+);
+CREATE INDEX edges_history_incoming_idx ON follows.edges_history (
+    subject_id, actor_id
+);
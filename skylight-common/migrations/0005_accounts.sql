@@ -0,0 +1,22 @@
+CREATE TABLE follows.accounts (
+    id INT NOT NULL PRIMARY KEY,
+    active BOOLEAN NOT NULL,
+    status TEXT,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+-- Edges of accounts that are deactivated, taken down, etc. are kept but hidden.
+CREATE VIEW follows.visible_edges AS
+SELECT e.*
+FROM follows.edges AS e
+WHERE
+    NOT EXISTS (
+        SELECT *
+        FROM follows.accounts AS a
+        WHERE a.id = e.actor_id AND NOT a.active
+    ) AND
+    NOT EXISTS (
+        SELECT *
+        FROM follows.accounts AS a
+        WHERE a.id = e.subject_id AND NOT a.active
+    );
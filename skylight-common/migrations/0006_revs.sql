@@ -0,0 +1,25 @@
+-- visible_edges selects e.*, so it has to be recreated to pick up the new column.
+DROP VIEW follows.visible_edges;
+
+ALTER TABLE follows.edges ADD COLUMN rev TEXT COLLATE "C";
+
+CREATE VIEW follows.visible_edges AS
+SELECT e.*
+FROM follows.edges AS e
+WHERE
+    NOT EXISTS (
+        SELECT *
+        FROM follows.accounts AS a
+        WHERE a.id = e.actor_id AND NOT a.active
+    ) AND
+    NOT EXISTS (
+        SELECT *
+        FROM follows.accounts AS a
+        WHERE a.id = e.subject_id AND NOT a.active
+    );
+
+-- The latest repo rev whose follows have been written, by either the crawler or the ingester.
+CREATE TABLE follows.revs (
+    actor_id INT NOT NULL PRIMARY KEY,
+    rev TEXT COLLATE "C" NOT NULL
+);
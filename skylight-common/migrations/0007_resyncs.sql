@@ -0,0 +1,7 @@
+CREATE TABLE followscrawler.resyncs (
+    did TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    seq BIGINT,
+    ts TIMESTAMPTZ DEFAULT NOW()
+);
+CREATE INDEX resyncs_did_idx ON followscrawler.resyncs (did);
@@ -0,0 +1,4 @@
+CREATE TABLE plc.refresh_queue (
+    did TEXT PRIMARY KEY,
+    ts TIMESTAMPTZ DEFAULT NOW()
+);
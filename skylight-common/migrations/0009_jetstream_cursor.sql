@@ -0,0 +1,5 @@
+-- Microseconds since the epoch of the last applied Jetstream event.
+CREATE TABLE follows.jetstream_cursor (
+    cursor BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX jetstream_cursor_single ON follows.jetstream_cursor ((0));
@@ -0,0 +1,38 @@
+-- Handles from the firehose's #identity and #handle events. These arrive well before plc.dids is
+-- refreshed, and also cover did:web accounts. A NULL handle means the account has no valid handle.
+CREATE TABLE plc.handles (
+    did TEXT PRIMARY KEY,
+    handle TEXT,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX handles_handle_idx ON plc.handles (handle);
+
+-- plc.dids, with at:// entries replaced by the handle in plc.handles where there is one.
+CREATE VIEW plc.identities AS
+SELECT
+    d.did,
+    CASE
+        WHEN h.did IS NULL THEN d.also_known_as
+        ELSE
+            (
+                CASE
+                    WHEN h.handle IS NULL THEN '{}'::TEXT []
+                    ELSE ARRAY['at://' || h.handle]
+                END
+            ) || ARRAY(
+                SELECT aka
+                FROM UNNEST(d.also_known_as) AS aka
+                WHERE aka NOT LIKE 'at://%'
+            )
+    END AS also_known_as
+FROM plc.dids AS d
+LEFT JOIN plc.handles AS h ON d.did = h.did
+UNION ALL
+SELECT
+    h.did,
+    CASE
+        WHEN h.handle IS NULL THEN '{}'::TEXT []
+        ELSE ARRAY['at://' || h.handle]
+    END AS also_known_as
+FROM plc.handles AS h
+WHERE NOT EXISTS (SELECT * FROM plc.dids AS d WHERE d.did = h.did);
@@ -0,0 +1,19 @@
+-- The last committed seq of each relay, or for Jetstream hosts, the time of the last committed
+-- event in microseconds.
+CREATE TABLE follows.cursors (
+    host TEXT PRIMARY KEY,
+    cursor BIGINT NOT NULL
+);
+
+-- There's no telling which host the single firehose and Jetstream cursors were for, so they're
+-- kept under these keys until the ingester hands them to the first host it's configured with.
+INSERT INTO follows.cursors (host, cursor)
+SELECT 'legacy:firehose', cursor
+FROM follows.cursor;
+
+INSERT INTO follows.cursors (host, cursor)
+SELECT 'legacy:jetstream', cursor
+FROM follows.jetstream_cursor;
+
+DROP TABLE follows.cursor;
+DROP TABLE follows.jetstream_cursor;
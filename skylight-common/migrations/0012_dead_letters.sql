@@ -0,0 +1,12 @@
+-- Frames that couldn't be decoded or applied, set aside so the stream keeps flowing. They can be
+-- applied again with --retry-dead-letters.
+CREATE TABLE follows.dead_letters (
+    id BIGSERIAL PRIMARY KEY,
+    host TEXT,
+    seq BIGINT,
+    repo TEXT,
+    jetstream BOOLEAN NOT NULL,
+    frame BYTEA NOT NULL,
+    error TEXT NOT NULL,
+    ts TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
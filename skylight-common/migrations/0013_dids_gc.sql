@@ -0,0 +1,12 @@
+-- Ids of DIDs that skylight-followsgc removed from follows.dids, to be assigned to new DIDs.
+CREATE TABLE follows.free_ids (
+    id INT PRIMARY KEY
+);
+
+-- Bumped each time skylight-followsgc removes DIDs, so that writers know to drop the ids they
+-- have cached.
+CREATE TABLE follows.dids_gc (
+    generation BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX dids_gc_single ON follows.dids_gc ((0));
+INSERT INTO follows.dids_gc (generation) VALUES (0);
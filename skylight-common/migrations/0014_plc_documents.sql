@@ -0,0 +1,11 @@
+-- The rest of each DID's current state. signing_key is the atproto verification method and
+-- pds_endpoint the endpoint of the atproto_pds service, pulled out for convenience. Rows written
+-- before these columns existed are filled in as their DIDs are next updated or refreshed.
+ALTER TABLE plc.dids
+ADD COLUMN rotation_keys TEXT [] NOT NULL DEFAULT '{}',
+ADD COLUMN verification_methods JSONB NOT NULL DEFAULT '{}',
+ADD COLUMN services JSONB NOT NULL DEFAULT '{}',
+ADD COLUMN signing_key TEXT,
+ADD COLUMN pds_endpoint TEXT;
+
+CREATE INDEX dids_pds_endpoint_idx ON plc.dids (pds_endpoint);
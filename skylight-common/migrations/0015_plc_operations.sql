@@ -0,0 +1,38 @@
+-- Every operation in every DID's log, as exported by plc.directory. Rows are only ever added, or
+-- marked nullified when a later operation forks the log from before them, e.g. a recovery using a
+-- higher-priority rotation key. plc.dids is the state as of the latest operation not nullified.
+CREATE TABLE plc.operations (
+    did TEXT NOT NULL,
+    cid TEXT NOT NULL,
+    prev TEXT,
+    type TEXT NOT NULL,
+    operation JSONB NOT NULL,
+    nullified BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (did, cid)
+);
+CREATE INDEX operations_did_created_at_idx ON plc.operations (did, created_at);
+
+-- The handles each DID has claimed over time, from operations that weren't nullified.
+CREATE VIEW plc.handle_history AS
+SELECT
+    o.did,
+    o.cid,
+    o.created_at,
+    SUBSTRING(aka FROM 6) AS handle
+FROM plc.operations AS o
+CROSS JOIN LATERAL JSONB_ARRAY_ELEMENTS_TEXT(o.operation -> 'alsoKnownAs') AS aka
+WHERE
+    o.type = 'plc_operation' AND
+    NOT o.nullified AND
+    aka LIKE 'at://%'
+UNION ALL
+SELECT
+    o.did,
+    o.cid,
+    o.created_at,
+    o.operation ->> 'handle' AS handle
+FROM plc.operations AS o
+WHERE
+    o.type = 'create' AND
+    NOT o.nullified;
@@ -0,0 +1,13 @@
+-- Export entries that failed verification, e.g. because their CID doesn't match their contents,
+-- their prev isn't in the log or their signature isn't by one of the rotation keys of prev. They
+-- are kept out of plc.operations, so nothing is derived from them.
+CREATE TABLE plc.quarantine (
+    did TEXT NOT NULL,
+    cid TEXT NOT NULL,
+    operation JSONB NOT NULL,
+    nullified BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    reason TEXT NOT NULL,
+    ts TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (did, cid)
+);
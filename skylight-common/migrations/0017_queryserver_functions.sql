@@ -0,0 +1,249 @@
+-- The functions behind skylight-queryserver's graph queries, which were applied by hand from
+-- skylight-queryserver/schema.sql. They were written in plpython3u, which only a superuser can
+-- install or write functions in, so they're redone here in SQL and PL/pgSQL.
+
+-- The accounts that are mutuals of all of ids.
+CREATE OR REPLACE FUNCTION follows.mutuals(
+    ids INT [],
+    ignore_ids INT []
+) RETURNS TABLE (id INT) AS $$
+    SELECT i.subject_id
+    FROM follows.visible_edges AS i
+    INNER JOIN follows.visible_edges AS o ON
+        i.actor_id = o.subject_id AND
+        i.subject_id = o.actor_id
+    WHERE
+        i.actor_id = ANY(ids) AND
+        i.subject_id != ALL(ignore_ids)
+    GROUP BY i.subject_id
+    HAVING COUNT(DISTINCT i.actor_id) = (SELECT COUNT(DISTINCT id) FROM UNNEST(ids) AS id)
+$$ LANGUAGE sql STABLE;
+
+-- The accounts that follow all of ids.
+CREATE OR REPLACE FUNCTION follows.incoming(
+    ids INT [],
+    ignore_ids INT []
+) RETURNS TABLE (id INT) AS $$
+    SELECT i.actor_id
+    FROM follows.visible_edges AS i
+    WHERE
+        i.subject_id = ANY(ids) AND
+        i.actor_id != ALL(ignore_ids)
+    GROUP BY i.actor_id
+    HAVING COUNT(DISTINCT i.subject_id) = (SELECT COUNT(DISTINCT id) FROM UNNEST(ids) AS id)
+$$ LANGUAGE sql STABLE;
+
+-- The accounts that are mutuals of all of ids, each with those of them it is a mutual of.
+CREATE OR REPLACE FUNCTION follows.neighborhood(
+    ids INT [],
+    ignore_ids INT []
+) RETURNS TABLE (actor_id INT, subject_ids INT []) AS $$
+    WITH mutuals AS (
+        SELECT m.id
+        FROM follows.mutuals(ids, ignore_ids) AS m
+    )
+    SELECT
+        a.id,
+        ARRAY(
+            SELECT DISTINCT i.subject_id
+            FROM follows.visible_edges AS i
+            INNER JOIN follows.visible_edges AS o ON
+                i.actor_id = o.subject_id AND
+                i.subject_id = o.actor_id
+            WHERE
+                i.actor_id = a.id AND
+                i.subject_id IN (SELECT id FROM mutuals)
+        )
+    FROM mutuals AS a
+$$ LANGUAGE sql STABLE;
+
+-- Paths of mutuals between two accounts are found by a breadth-first search from both ends at
+-- once, which follows.next_paths advances a few paths at a time. The search is kept in temporary
+-- tables, so it belongs to the session that started it:
+--
+-- - paths_search: the two ends, the ids to avoid and how many nodes have been expanded.
+-- - paths_visited: the nodes reached from each end, each with the node it was reached from.
+-- - paths_queue: the nodes of each end still to be expanded, in order.
+-- - paths_found: paths found but not yet returned, in order.
+
+-- Starts a search for paths from source_id to target_id that avoid ignore_ids, replacing any the
+-- session had.
+CREATE OR REPLACE FUNCTION follows.set_paths_generator(
+    source_id INT,
+    target_id INT,
+    ignore_ids INT []
+) RETURNS VOID AS $$
+BEGIN
+    IF to_regclass('pg_temp.paths_search') IS NULL THEN
+        CREATE TEMPORARY TABLE paths_search (
+            source INT NOT NULL,
+            target INT NOT NULL,
+            ignored INT [] NOT NULL,
+            expanded INT NOT NULL,
+            source_queued INT NOT NULL,
+            target_queued INT NOT NULL
+        );
+        CREATE TEMPORARY TABLE paths_visited (
+            from_source BOOLEAN NOT NULL,
+            node INT NOT NULL,
+            parent INT,
+            PRIMARY KEY (from_source, node)
+        );
+        CREATE TEMPORARY TABLE paths_queue (
+            seq BIGSERIAL PRIMARY KEY,
+            from_source BOOLEAN NOT NULL,
+            node INT NOT NULL
+        );
+        CREATE TEMPORARY TABLE paths_found (
+            seq BIGSERIAL PRIMARY KEY,
+            found INT [] NOT NULL,
+            expanded INT NOT NULL
+        );
+    END IF;
+    TRUNCATE pg_temp.paths_search, pg_temp.paths_visited, pg_temp.paths_queue, pg_temp.paths_found;
+
+    IF source_id = target_id THEN
+        INSERT INTO pg_temp.paths_search VALUES (source_id, target_id, ignore_ids, 0, 0, 0);
+        INSERT INTO pg_temp.paths_found (found, expanded) VALUES (ARRAY[source_id], 0);
+        RETURN;
+    END IF;
+
+    -- Check for direct intersection.
+    IF EXISTS (
+        SELECT *
+        FROM follows.visible_edges AS i
+        INNER JOIN follows.visible_edges AS o ON
+            i.actor_id = o.subject_id AND
+            i.subject_id = o.actor_id
+        WHERE
+            i.actor_id = source_id AND
+            i.subject_id = target_id
+    ) THEN
+        INSERT INTO pg_temp.paths_found (found, expanded) VALUES (ARRAY[source_id, target_id], 0);
+    END IF;
+
+    INSERT INTO pg_temp.paths_search VALUES (source_id, target_id, ignore_ids, 0, 1, 1);
+    INSERT INTO pg_temp.paths_visited VALUES (TRUE, source_id, NULL), (FALSE, target_id, NULL);
+    INSERT INTO pg_temp.paths_queue (from_source, node) VALUES (TRUE, source_id), (FALSE, target_id);
+END
+$$ LANGUAGE plpgsql;
+
+-- Drops the session's search.
+CREATE OR REPLACE FUNCTION follows.clear_paths_generator()
+RETURNS VOID AS $$
+BEGIN
+    DROP TABLE IF EXISTS
+        pg_temp.paths_search,
+        pg_temp.paths_visited,
+        pg_temp.paths_queue,
+        pg_temp.paths_found;
+END
+$$ LANGUAGE plpgsql;
+
+-- Returns up to the next n paths of the session's search, each with how many nodes had been
+-- expanded when it was found. Fewer than n means the search is over.
+CREATE OR REPLACE FUNCTION follows.next_paths(
+    n INT
+) RETURNS TABLE (path INT [], nodes_expanded INT) AS $$
+DECLARE
+    search RECORD;
+    item RECORD;
+    toward INT;
+    neighbor INT;
+    source_half INT [];
+    target_half INT [];
+BEGIN
+    IF to_regclass('pg_temp.paths_search') IS NULL THEN
+        RAISE EXCEPTION 'set_paths_generator was not called in this session';
+    END IF;
+    SELECT * INTO STRICT search FROM pg_temp.paths_search;
+
+    WHILE
+        (SELECT COUNT(*) FROM pg_temp.paths_found) < n AND
+        search.source_queued > 0 AND
+        search.target_queued > 0
+    LOOP
+        -- Expand the next node of whichever end has the shorter queue.
+        SELECT * INTO STRICT item
+        FROM pg_temp.paths_queue AS q
+        WHERE q.from_source = (search.source_queued <= search.target_queued)
+        ORDER BY q.seq
+        LIMIT 1;
+        DELETE FROM pg_temp.paths_queue AS q WHERE q.seq = item.seq;
+        IF item.from_source THEN
+            search.source_queued := search.source_queued - 1;
+            toward := search.target;
+        ELSE
+            search.target_queued := search.target_queued - 1;
+            toward := search.source;
+        END IF;
+
+        FOR neighbor IN
+            SELECT m.id
+            FROM follows.mutuals(ARRAY[item.node], search.ignored) AS m
+        LOOP
+            CONTINUE WHEN neighbor = toward OR EXISTS (
+                SELECT *
+                FROM pg_temp.paths_visited AS v
+                WHERE v.from_source = item.from_source AND v.node = neighbor
+            );
+            INSERT INTO pg_temp.paths_visited VALUES (item.from_source, neighbor, item.node);
+            search.expanded := search.expanded + 1;
+
+            INSERT INTO pg_temp.paths_queue (from_source, node) VALUES (item.from_source, neighbor);
+            IF item.from_source THEN
+                search.source_queued := search.source_queued + 1;
+            ELSE
+                search.target_queued := search.target_queued + 1;
+            END IF;
+
+            IF EXISTS (
+                SELECT *
+                FROM pg_temp.paths_visited AS v
+                WHERE v.from_source = NOT item.from_source AND v.node = neighbor
+            ) THEN
+                -- Both ends have reached neighbor: walk back from it to each of them.
+                WITH RECURSIVE walk (node, parent, depth) AS (
+                    SELECT v.node, v.parent, 0
+                    FROM pg_temp.paths_visited AS v
+                    WHERE v.from_source AND v.node = neighbor
+                    UNION ALL
+                    SELECT v.node, v.parent, w.depth + 1
+                    FROM walk AS w
+                    INNER JOIN pg_temp.paths_visited AS v ON v.from_source AND v.node = w.parent
+                )
+                SELECT ARRAY_AGG(w.node ORDER BY w.depth DESC) INTO source_half FROM walk AS w;
+                WITH RECURSIVE walk (node, parent, depth) AS (
+                    SELECT v.node, v.parent, 0
+                    FROM pg_temp.paths_visited AS v
+                    WHERE NOT v.from_source AND v.node = neighbor
+                    UNION ALL
+                    SELECT v.node, v.parent, w.depth + 1
+                    FROM walk AS w
+                    INNER JOIN pg_temp.paths_visited AS v ON NOT v.from_source AND v.node = w.parent
+                )
+                SELECT ARRAY_AGG(w.node ORDER BY w.depth) INTO target_half
+                FROM walk AS w
+                WHERE w.depth > 0;
+                INSERT INTO pg_temp.paths_found (found, expanded)
+                VALUES (source_half || COALESCE(target_half, '{}'), search.expanded);
+            END IF;
+        END LOOP;
+    END LOOP;
+
+    UPDATE pg_temp.paths_search SET
+        expanded = search.expanded,
+        source_queued = search.source_queued,
+        target_queued = search.target_queued;
+
+    RETURN QUERY
+    WITH returned AS (
+        DELETE FROM pg_temp.paths_found AS f
+        WHERE f.seq IN (SELECT f.seq FROM pg_temp.paths_found AS f ORDER BY f.seq LIMIT n)
+        RETURNING f.seq, f.found, f.expanded
+    )
+    SELECT r.found, r.expanded
+    FROM returned AS r
+    ORDER BY r.seq;
+END
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,62 @@
+-- follows.visible_edges as of a point in time, from follows.edges and follows.edges_history. An
+-- edge counts from its record's createdAt, or from when it was first seen if that's earlier or
+-- the record had none. Account status has no history, so edges of accounts that are inactive now
+-- are hidden at every point in time.
+CREATE FUNCTION follows.visible_edges_as_of(
+    at TIMESTAMPTZ
+) RETURNS TABLE (actor_id INT, subject_id INT) AS $$
+    SELECT e.actor_id, e.subject_id
+    FROM follows.visible_edges AS e
+    WHERE LEAST(e.created_at, e.first_seen) <= at
+    UNION ALL
+    SELECT h.actor_id, h.subject_id
+    FROM follows.edges_history AS h
+    WHERE
+        LEAST(h.created_at, h.first_seen) <= at AND
+        h.deleted_at > at AND
+        NOT EXISTS (
+            SELECT *
+            FROM follows.accounts AS a
+            WHERE a.id = h.actor_id AND NOT a.active
+        ) AND
+        NOT EXISTS (
+            SELECT *
+            FROM follows.accounts AS a
+            WHERE a.id = h.subject_id AND NOT a.active
+        )
+$$ LANGUAGE sql STABLE;
+
+-- follows.neighborhood as of a point in time: the accounts that were mutuals of all of ids, each
+-- with those of them it was a mutual of.
+CREATE FUNCTION follows.neighborhood_as_of(
+    ids INT [],
+    ignore_ids INT [],
+    at TIMESTAMPTZ
+) RETURNS TABLE (actor_id INT, subject_ids INT []) AS $$
+    WITH mutuals AS (
+        SELECT o.subject_id AS id
+        FROM follows.visible_edges_as_of(at) AS o
+        INNER JOIN follows.visible_edges_as_of(at) AS i ON
+            o.actor_id = i.subject_id AND
+            o.subject_id = i.actor_id
+        WHERE
+            o.actor_id = ANY(ids) AND
+            o.subject_id != ALL(ignore_ids)
+        GROUP BY o.subject_id
+        HAVING
+            COUNT(DISTINCT o.actor_id) = (SELECT COUNT(DISTINCT id) FROM UNNEST(ids) AS id)
+    )
+    SELECT
+        a.id,
+        ARRAY(
+            SELECT DISTINCT o.subject_id
+            FROM follows.visible_edges_as_of(at) AS o
+            INNER JOIN follows.visible_edges_as_of(at) AS i ON
+                o.actor_id = i.subject_id AND
+                o.subject_id = i.actor_id
+            WHERE
+                o.actor_id = a.id AND
+                o.subject_id IN (SELECT id FROM mutuals)
+        )
+    FROM mutuals AS a
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,133 @@
+//! Where each stream was last read up to, so it can be resumed after a restart.
+
+/// The last committed seq of a relay, or for Jetstream hosts, the time of the last committed event
+/// in microseconds.
+pub async fn relay<'c>(
+    executor: impl sqlx::PgExecutor<'c>,
+    host: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    Ok(
+        sqlx::query!("SELECT cursor FROM follows.cursors WHERE host = $1", host)
+            .fetch_optional(executor)
+            .await?
+            .map(|v| v.cursor),
+    )
+}
+
+pub async fn set_relay<'c>(
+    executor: impl sqlx::PgExecutor<'c>,
+    host: &str,
+    cursor: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO follows.cursors (host, cursor)
+        VALUES ($1, $2)
+        ON CONFLICT (host) DO
+        UPDATE SET cursor = excluded.cursor
+        "#,
+        host,
+        cursor
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Forgets a relay's cursor, so the next subscription starts from the live stream.
+pub async fn clear_relay<'c>(
+    executor: impl sqlx::PgExecutor<'c>,
+    host: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM follows.cursors WHERE host = $1", host)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Hands the cursor the firehose or Jetstream subscription had before cursors were kept per host,
+/// which migration 0011 set aside, to `host`, unless it already has one. Returns the cursor if it
+/// was carried over.
+pub async fn adopt_legacy_relay<'c>(
+    executor: impl sqlx::PgExecutor<'c>,
+    host: &str,
+    jetstream: bool,
+) -> Result<Option<i64>, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"--sql
+        WITH legacy AS (
+            DELETE FROM follows.cursors
+            WHERE host = $2
+            RETURNING cursor
+        )
+        INSERT INTO follows.cursors (host, cursor)
+        SELECT $1, cursor
+        FROM legacy
+        ON CONFLICT (host) DO NOTHING
+        RETURNING cursor
+        "#,
+        host,
+        if jetstream {
+            "legacy:jetstream"
+        } else {
+            "legacy:firehose"
+        }
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(|v| v.cursor))
+}
+
+/// The listRepos cursor of the crawler. An empty cursor means listing has finished.
+pub async fn crawler<'c>(
+    executor: impl sqlx::PgExecutor<'c>,
+) -> Result<Option<String>, sqlx::Error> {
+    Ok(sqlx::query!("SELECT cursor FROM followscrawler.cursor")
+        .fetch_optional(executor)
+        .await?
+        .map(|v| v.cursor))
+}
+
+pub async fn set_crawler<'c>(
+    executor: impl sqlx::PgExecutor<'c>,
+    cursor: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO followscrawler.cursor (cursor)
+        VALUES ($1)
+        ON CONFLICT ((0)) DO
+        UPDATE SET cursor = excluded.cursor
+        "#,
+        cursor
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// The `createdAt` of the last PLC export entry ingested.
+pub async fn plc<'c>(executor: impl sqlx::PgExecutor<'c>) -> Result<Option<String>, sqlx::Error> {
+    Ok(sqlx::query!("SELECT cursor FROM plc.cursor")
+        .fetch_optional(executor)
+        .await?
+        .map(|v| v.cursor))
+}
+
+pub async fn set_plc<'c>(
+    executor: impl sqlx::PgExecutor<'c>,
+    cursor: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO plc.cursor (cursor)
+        VALUES ($1)
+        ON CONFLICT ((0)) DO
+        UPDATE SET cursor = excluded.cursor
+        "#,
+        cursor
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
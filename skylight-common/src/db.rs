@@ -0,0 +1,26 @@
+/// Connects to the database at `dsn`. With `migrate`, pending migrations are applied first;
+/// either way, the connection is refused if the schema isn't the one this binary was built
+/// against.
+///
+/// Also returns the parsed options, for opening further connections.
+pub async fn connect(
+    dsn: &str,
+    migrate: bool,
+) -> Result<
+    (
+        sqlx::postgres::PgConnectOptions,
+        sqlx::postgres::PgConnection,
+    ),
+    crate::schema::Error,
+> {
+    use sqlx::Connection;
+    use std::str::FromStr;
+
+    let conn_options = sqlx::postgres::PgConnectOptions::from_str(dsn)?;
+    let mut conn = sqlx::postgres::PgConnection::connect_with(&conn_options).await?;
+    if migrate {
+        crate::schema::migrate(&mut conn).await?;
+    }
+    crate::schema::check(&mut conn).await?;
+    Ok((conn_options, conn))
+}
@@ -0,0 +1,214 @@
+//! Assigns each DID a small integer id in follows.dids, for writers to refer to it by.
+//!
+//! DIDs that nothing refers to any more are removed by skylight-followsgc, and their ids handed out
+//! again. To keep this from racing with writers, every transaction that writes ids takes
+//! [`GC_LOCK`] shared, and the collector takes it exclusively. Each removal also bumps
+//! follows.dids_gc, which tells writers to drop the ids they have cached.
+
+/// Advisory lock key held shared by writers of ids and exclusively by the collector.
+pub const GC_LOCK: i64 = 0x736b796c69676874;
+
+/// Holds off the collector until the end of the current transaction. Writers that don't go
+/// through a [`DidIdAssigner`], e.g. ones inserting into follows.dids directly, must call this
+/// before doing so.
+pub async fn hold_off_gc(conn: &mut sqlx::postgres::PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT pg_advisory_xact_lock_shared($1)", GC_LOCK)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+struct Cached {
+    ids: lru::LruCache<String, i32>,
+    generation: i64,
+}
+
+/// DID→id mappings recently looked up, shared between the writers of one process.
+pub struct DidIdCache {
+    cached: std::sync::Mutex<Cached>,
+    metric: &'static str,
+}
+
+impl DidIdCache {
+    /// Creates a cache holding up to `capacity` DIDs. Hits and misses are counted in the
+    /// `metric` counter, labelled with `result`.
+    pub fn new(capacity: std::num::NonZeroUsize, metric: &'static str) -> Self {
+        Self {
+            cached: std::sync::Mutex::new(Cached {
+                ids: lru::LruCache::new(capacity),
+                generation: 0,
+            }),
+            metric,
+        }
+    }
+
+    fn get(&self, did: &str) -> Option<i32> {
+        let id = self.cached.lock().unwrap().ids.get(did).copied();
+        metrics::increment_counter!(self.metric, "result" => if id.is_some() { "hit" } else { "miss" });
+        id
+    }
+
+    fn put(&self, did: String, id: i32) {
+        self.cached.lock().unwrap().ids.put(did, id);
+    }
+
+    /// Drops every cached id if the collector has run since the cache was last emptied.
+    fn observe_generation(&self, generation: i64) {
+        let mut cached = self.cached.lock().unwrap();
+        if cached.generation != generation {
+            cached.ids.clear();
+            cached.generation = generation;
+        }
+    }
+}
+
+/// Looks up DIDs' ids through a [`DidIdCache`], assigning ids to DIDs that don't have one yet.
+///
+/// Ids are assigned on the assigner's own connection, outside of any transaction the caller has
+/// open, so that a cached id is never one whose row was rolled back.
+pub struct DidIdAssigner {
+    conn: sqlx::postgres::PgConnection,
+    cache: std::sync::Arc<DidIdCache>,
+}
+
+impl DidIdAssigner {
+    pub fn new(conn: sqlx::postgres::PgConnection, cache: std::sync::Arc<DidIdCache>) -> Self {
+        Self { conn, cache }
+    }
+
+    /// Must be called in every transaction that writes ids from this assigner, before assigning
+    /// any. Holds off the collector until the transaction ends, and drops cached ids it may have
+    /// removed.
+    pub async fn begin(
+        &mut self,
+        conn: &mut sqlx::postgres::PgConnection,
+    ) -> Result<(), sqlx::Error> {
+        hold_off_gc(conn).await?;
+        // Read after the lock is taken, so that a collection that just finished is seen.
+        let generation = sqlx::query!("SELECT generation FROM follows.dids_gc")
+            .fetch_optional(conn)
+            .await?
+            .map(|v| v.generation)
+            .unwrap_or(0);
+        self.cache.observe_generation(generation);
+        Ok(())
+    }
+
+    pub async fn assign(&mut self, did: &str) -> Result<i32, sqlx::Error> {
+        if let Some(id) = self.cache.get(did) {
+            return Ok(id);
+        }
+        self.assign_many(&[did])
+            .await?
+            .get(did)
+            .copied()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    /// Looks up the ids of `dids` in as few round trips as possible. Rows are only written for
+    /// DIDs that don't have one yet.
+    pub async fn assign_many(
+        &mut self,
+        dids: &[&str],
+    ) -> Result<std::collections::HashMap<String, i32>, sqlx::Error> {
+        let mut ids = std::collections::HashMap::with_capacity(dids.len());
+        let mut missing = std::collections::BTreeSet::new();
+        for did in dids {
+            if ids.contains_key(*did) || missing.contains(*did) {
+                continue;
+            }
+            match self.cache.get(did) {
+                Some(id) => {
+                    ids.insert(did.to_string(), id);
+                }
+                None => {
+                    missing.insert(did.to_string());
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            self.select(&mut missing, &mut ids).await?;
+        }
+
+        if !missing.is_empty() {
+            // Ids freed by the collector are used up first. Rows are inserted in order of DID, so
+            // that writers inserting overlapping DIDs take their locks in the same order. If
+            // another writer inserts one of the DIDs first, the free id taken for it is lost.
+            let inserted = sqlx::query!(
+                r#"--sql
+                WITH
+                input AS (
+                    SELECT did, ROW_NUMBER() OVER (ORDER BY did) AS n
+                    FROM UNNEST($1::TEXT[]) AS did
+                ),
+
+                free AS (
+                    DELETE FROM follows.free_ids
+                    WHERE id IN (
+                        SELECT id
+                        FROM follows.free_ids
+                        LIMIT (SELECT COUNT(*) FROM input)
+                        FOR UPDATE
+                        SKIP LOCKED
+                    )
+                    RETURNING id
+                ),
+
+                numbered AS (
+                    SELECT id, ROW_NUMBER() OVER (ORDER BY id) AS n
+                    FROM free
+                )
+
+                INSERT INTO follows.dids (id, did)
+                SELECT
+                    COALESCE(numbered.id, nextval('follows.dids_id_seq')::INT),
+                    input.did
+                FROM input
+                LEFT JOIN numbered ON input.n = numbered.n
+                ORDER BY input.did
+                ON CONFLICT (did) DO NOTHING
+                RETURNING did, id
+                "#,
+                &missing.iter().cloned().collect::<Vec<_>>()
+            )
+            .fetch_all(&mut self.conn)
+            .await?;
+            for row in inserted {
+                missing.remove(&row.did);
+                self.cache.put(row.did.clone(), row.id);
+                ids.insert(row.did, row.id);
+            }
+        }
+
+        if !missing.is_empty() {
+            // Another writer inserted these between our select and insert.
+            self.select(&mut missing, &mut ids).await?;
+        }
+
+        Ok(ids)
+    }
+
+    async fn select(
+        &mut self,
+        missing: &mut std::collections::BTreeSet<String>,
+        ids: &mut std::collections::HashMap<String, i32>,
+    ) -> Result<(), sqlx::Error> {
+        let found = sqlx::query!(
+            r#"--sql
+            SELECT did, id
+            FROM follows.dids
+            WHERE did = ANY($1)
+            "#,
+            &missing.iter().cloned().collect::<Vec<_>>()
+        )
+        .fetch_all(&mut self.conn)
+        .await?;
+        for row in found {
+            missing.remove(&row.did);
+            self.cache.put(row.did.clone(), row.id);
+            ids.insert(row.did, row.id);
+        }
+        Ok(())
+    }
+}
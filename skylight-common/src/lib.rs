@@ -0,0 +1,7 @@
+pub mod cursors;
+pub mod db;
+pub mod did_ids;
+pub mod logging;
+pub mod recording;
+pub mod schema;
+pub mod shutdown;
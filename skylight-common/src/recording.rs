@@ -0,0 +1,135 @@
+//! Raw websocket frames as received, for replaying later without a network.
+//!
+//! A recording is a sequence of frames, each written as the time it was received in microseconds
+//! since the epoch (i64), the frame's kind (u8: 0 for binary, 1 for text), its length (u32) and
+//! its bytes. All integers are big endian.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const KIND_BINARY: u8 = 0;
+const KIND_TEXT: u8 = 1;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("unknown frame kind: {0}")]
+    UnknownKind(u8),
+
+    #[error("text frame is not utf-8: {0}")]
+    Utf8(#[from] std::string::FromUtf8Error),
+}
+
+/// A recorded data frame.
+pub enum Frame {
+    Binary(Vec<u8>),
+    Text(String),
+}
+
+/// Writes frames to files in a directory, starting a new file once the current one is larger than
+/// `max_bytes`. Files are named after the time they were started, so they sort in order.
+pub struct Recorder {
+    dir: std::path::PathBuf,
+    max_bytes: u64,
+    file: Option<tokio::io::BufWriter<tokio::fs::File>>,
+    written: u64,
+}
+
+impl Recorder {
+    pub fn new(dir: std::path::PathBuf, max_bytes: u64) -> Self {
+        Self {
+            dir,
+            max_bytes,
+            file: None,
+            written: 0,
+        }
+    }
+
+    pub async fn write_binary(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.write(KIND_BINARY, buf).await
+    }
+
+    pub async fn write_text(&mut self, text: &str) -> Result<(), Error> {
+        self.write(KIND_TEXT, text.as_bytes()).await
+    }
+
+    async fn write(&mut self, kind: u8, buf: &[u8]) -> Result<(), Error> {
+        if self.written >= self.max_bytes {
+            self.flush().await?;
+            self.file = None;
+        }
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let now = time::OffsetDateTime::now_utc();
+                let path = self.dir.join(format!(
+                    "frames-{:020}.bin",
+                    now.unix_timestamp_nanos() / 1000
+                ));
+                tracing::info!(action = "start recording", path = ?path);
+                self.written = 0;
+                self.file.insert(tokio::io::BufWriter::new(
+                    tokio::fs::File::create(path).await?,
+                ))
+            }
+        };
+
+        let received_us = (time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1000) as i64;
+        write_frame(file, received_us, kind, buf).await?;
+        self.written += 8 + 1 + 4 + buf.len() as u64;
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        if let Some(file) = &mut self.file {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn write_frame(
+    w: &mut (impl tokio::io::AsyncWrite + std::marker::Unpin),
+    received_us: i64,
+    kind: u8,
+    buf: &[u8],
+) -> Result<(), Error> {
+    w.write_i64(received_us).await?;
+    w.write_u8(kind).await?;
+    w.write_u32(buf.len() as u32).await?;
+    w.write_all(buf).await?;
+    Ok(())
+}
+
+/// Reads the next frame of a recording along with the time it was received in microseconds, or
+/// `None` at the end of the file.
+pub async fn read_frame(
+    r: &mut (impl tokio::io::AsyncRead + std::marker::Unpin),
+) -> Result<Option<(i64, Frame)>, Error> {
+    let received_us = match r.read_i64().await {
+        Ok(received_us) => received_us,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e.into());
+        }
+    };
+    let kind = r.read_u8().await?;
+    let len = r.read_u32().await?;
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf).await?;
+
+    Ok(Some((
+        received_us,
+        match kind {
+            KIND_BINARY => Frame::Binary(buf),
+            KIND_TEXT => Frame::Text(String::from_utf8(buf)?),
+            kind => {
+                return Err(Error::UnknownKind(kind));
+            }
+        },
+    )))
+}
@@ -0,0 +1,89 @@
+//! The follows, followscrawler and plc schemas, as versioned migrations embedded in every binary.
+//!
+//! Migrations live in `migrations/` and are applied in order of their numeric prefix. Once a
+//! migration has been released it must not be edited, only followed by a new one.
+//!
+//! The first three recreate the schemas as they were applied by hand from each binary's
+//! `schema.sql`, and only create what doesn't exist yet. A database set up that way is adopted by
+//! running any binary once with `--migrate`, which records it as migrated and upgrades it.
+
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("sqlx: {0}")]
+    Sqlx(#[from] sqlx::Error),
+
+    #[error("migrate: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+
+    #[error("database has no migration history, run with --migrate to create or adopt its schema")]
+    Missing,
+
+    #[error("database schema is missing migration {0}, run with --migrate to apply it")]
+    Behind(i64),
+
+    #[error("database schema has migration {0}, which is newer than this binary")]
+    Ahead(i64),
+
+    #[error("migration {0} was only partly applied")]
+    Dirty(i64),
+
+    #[error("migration {0} was changed after it was applied")]
+    Modified(i64),
+}
+
+/// The latest schema version this binary knows about.
+pub fn version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+/// Applies any migrations the database doesn't have yet.
+pub async fn migrate(conn: &mut sqlx::postgres::PgConnection) -> Result<(), Error> {
+    MIGRATOR.run(conn).await?;
+    tracing::info!(version = version(), "migrated schema");
+    Ok(())
+}
+
+/// Checks that the database has exactly the migrations this binary was built with, unchanged.
+pub async fn check(conn: &mut sqlx::postgres::PgConnection) -> Result<(), Error> {
+    use sqlx::migrate::Migrate;
+
+    if !sqlx::query!(
+        r#"--sql
+        SELECT to_regclass('public._sqlx_migrations') IS NOT NULL AS "exists!"
+        "#
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .exists
+    {
+        return Err(Error::Missing);
+    }
+
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(Error::Dirty(version));
+    }
+
+    let applied = conn.list_applied_migrations().await?;
+    if applied.is_empty() {
+        return Err(Error::Missing);
+    }
+    for applied in &applied {
+        match MIGRATOR.iter().find(|m| m.version == applied.version) {
+            Some(migration) if migration.checksum != applied.checksum => {
+                return Err(Error::Modified(applied.version));
+            }
+            Some(_) => {}
+            None => {
+                return Err(Error::Ahead(applied.version));
+            }
+        }
+    }
+    for migration in MIGRATOR.iter() {
+        if !applied.iter().any(|a| a.version == migration.version) {
+            return Err(Error::Behind(migration.version));
+        }
+    }
+    Ok(())
+}
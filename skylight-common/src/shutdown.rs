@@ -0,0 +1,40 @@
+//! Graceful shutdown on SIGINT or SIGTERM.
+
+/// When shutdown was first observed, which every deadline is counted from.
+static REQUESTED_AT: std::sync::OnceLock<tokio::time::Instant> = std::sync::OnceLock::new();
+
+fn requested_at() -> tokio::time::Instant {
+    *REQUESTED_AT.get_or_init(tokio::time::Instant::now)
+}
+
+/// Resolves on SIGINT or SIGTERM.
+pub async fn signal() {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("installing SIGTERM handler failed");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+
+/// A receiver that turns true on SIGINT or SIGTERM, for tasks to check or wait on.
+pub fn watch() -> tokio::sync::watch::Receiver<bool> {
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        signal().await;
+        requested_at();
+        tracing::info!("shutting down");
+        shutdown_tx.send_replace(true);
+    });
+    shutdown_rx
+}
+
+/// Resolves once `timeout` has passed since shutdown was requested. The deadline is the same
+/// however many times this is called, so retrying after it has passed doesn't buy more time.
+pub async fn deadline(
+    shutdown: &mut tokio::sync::watch::Receiver<bool>,
+    timeout: std::time::Duration,
+) {
+    let _ = shutdown.wait_for(|v| *v).await;
+    tokio::time::sleep_until(requested_at() + timeout).await;
+}
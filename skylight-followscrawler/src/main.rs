@@ -1,5 +1,3 @@
-use std::str::FromStr;
-
 use clap::Parser;
 use futures::TryStreamExt;
 use sqlx::Connection;
@@ -11,6 +9,11 @@ struct Args {
     #[arg(long, default_value = "postgres:///skylight")]
     dsn: String,
 
+    /// Apply pending schema migrations before starting. Without this, the binary refuses to run
+    /// against a database whose schema isn't the version it was built against.
+    #[arg(long, default_value_t = false)]
+    migrate: bool,
+
     #[arg(long, default_value = "https://bsky.social")]
     pds_host: String,
 
@@ -19,212 +22,520 @@ struct Args {
 
     #[arg(long, default_value_t = false)]
     only_crawl_queued_repos: bool,
+
+    /// Move deleted edges into follows.edges_history instead of discarding them.
+    #[arg(long, default_value_t = false)]
+    record_edge_history: bool,
+
+    #[arg(long, default_value = "127.0.0.1:9001")]
+    prometheus_listen: std::net::SocketAddr,
+
+    /// Seconds to let in-flight crawls finish after SIGTERM before rolling them back.
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout: u64,
+}
+
+/// How many DIDs to cache the ids of, across all workers.
+const DID_ID_CACHE_CAPACITY: usize = 1_000_000;
+
+async fn wait_for_rate_limit(rl: &governor::DefaultDirectRateLimiter) {
+    let start = std::time::Instant::now();
+    rl.until_ready().await;
+    metrics::histogram!(
+        "skylight_followscrawler.ratelimit_wait",
+        start.elapsed().as_secs_f64()
+    );
+}
+
+async fn get(
+    client: &reqwest::Client,
+    url: reqwest::Url,
+    endpoint: &'static str,
+) -> Result<reqwest::Response, anyhow::Error> {
+    let host = url.host_str().unwrap_or_default().to_string();
+    let start = std::time::Instant::now();
+    let result = tokio::time::timeout(
+        std::time::Duration::from_secs(10 * 60),
+        client.get(url).send(),
+    )
+    .await;
+    // Failed requests are recorded too, or the slowest of them, timeouts, would never show up.
+    let status = match &result {
+        Ok(Ok(resp)) => resp.status().as_str().to_string(),
+        Ok(Err(e)) if e.is_timeout() => "timeout".to_string(),
+        Ok(Err(_)) => "error".to_string(),
+        Err(_) => "timeout".to_string(),
+    };
+    metrics::histogram!(
+        "skylight_followscrawler.request_duration",
+        start.elapsed().as_secs_f64(),
+        "host" => host,
+        "endpoint" => endpoint,
+        "status" => status
+    );
+    Ok(result??.error_for_status()?)
 }
 
-struct DidIdAssginer {
-    conn: sqlx::postgres::PgConnection,
+fn error_category(err: &anyhow::Error) -> &'static str {
+    if err.is::<tokio::time::error::Elapsed>() {
+        return "timeout";
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return if err.is_timeout() {
+            "timeout"
+        } else if err.is_status() {
+            "http_status"
+        } else {
+            "http"
+        };
+    }
+    if err.is::<atproto_repo::blockstore::Error>() {
+        return "repo";
+    }
+    if err.is::<sqlx::Error>() {
+        return "database";
+    }
+    "other"
 }
 
-impl DidIdAssginer {
-    async fn assign(&mut self, did: &str) -> Result<i32, sqlx::Error> {
-        Ok(sqlx::query!(
+async fn report_pending(
+    conn_options: &sqlx::postgres::PgConnectOptions,
+) -> Result<(), anyhow::Error> {
+    let mut conn = sqlx::postgres::PgConnection::connect_with(conn_options).await?;
+    loop {
+        let n = sqlx::query!(
+            r#"--sql
+            SELECT COUNT(*) AS "count!"
+            FROM followscrawler.pending
+            "#
+        )
+        .fetch_one(&mut conn)
+        .await?
+        .count;
+        metrics::gauge!("skylight_followscrawler.pending", n as f64);
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+    }
+}
+
+/// Keeps the pending gauge up to date, reconnecting after errors so that it doesn't go stale.
+async fn report_pending_main(conn_options: sqlx::postgres::PgConnectOptions) {
+    loop {
+        if let Err(e) = report_pending(&conn_options).await {
+            tracing::error!(error = format!("{e:?}"), "reporting pending repos failed");
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+    }
+}
+
+async fn crawl_repo(
+    pds_host: &str,
+    client: &reqwest::Client,
+    rl: &governor::DefaultDirectRateLimiter,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did: &str,
+    record_edge_history: bool,
+) -> Result<(), anyhow::Error> {
+    let mut blockstore_loader = atproto_repo::blockstore::Loader::new();
+    blockstore_loader.mst_ignore_missing(true);
+
+    wait_for_rate_limit(rl).await;
+    let repo = tokio::time::timeout(
+        std::time::Duration::from_secs(30 * 60),
+        blockstore_loader.load(
+            &mut get(
+                client,
+                reqwest::Url::parse(&format!(
+                    "{}/xrpc/com.atproto.sync.getRepo?did={}",
+                    pds_host, did
+                ))?,
+                "getRepo",
+            )
+            .await?
+            .bytes_stream()
+            .inspect_ok(|b| {
+                metrics::counter!("skylight_followscrawler.bytes_downloaded", b.len() as u64)
+            })
+            .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e))
+            .into_async_read(),
+        ),
+    )
+    .await??;
+
+    let mut records = vec![];
+    for (key, cid) in repo.key_and_cids() {
+        let key = String::from_utf8_lossy(key);
+        let (collection, rkey) = match key.splitn(2, '/').collect::<Vec<_>>()[..] {
+            [collection, rkey] => (collection, rkey),
+            _ => {
+                continue;
+            }
+        };
+
+        if collection != "app.bsky.graph.follow" {
+            continue;
+        }
+
+        let block = if let Some(block) = repo.get_by_cid(cid) {
+            block
+        } else {
+            continue;
+        };
+
+        #[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+        #[serde(rename_all = "camelCase")]
+        struct Record {
+            created_at: String,
+            subject: String,
+        }
+
+        let record: Record = match ciborium::from_reader(std::io::Cursor::new(block)) {
+            Ok(record) => record,
+            Err(e) => {
+                tracing::error!(error = format!("ciborium::from_reader: {e:?}"));
+                continue;
+            }
+        };
+        records.push((rkey.to_string(), record));
+    }
+
+    // Look up the ids of the repo and every subject at once.
+    did_id_assigner.begin(tx).await?;
+    let ids = did_id_assigner
+        .assign_many(
+            &std::iter::once(did)
+                .chain(records.iter().map(|(_, record)| record.subject.as_str()))
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+    let actor_id = ids[did];
+    let rev = repo.rev();
+
+    let n = records.len();
+    let mut subtx = tx.begin().await?;
+
+    // Lock the repo's rev watermark so the ingester can't apply ops for this repo until the
+    // snapshot is written.
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO follows.revs (actor_id, rev)
+        VALUES ($1, '')
+        ON CONFLICT DO NOTHING
+        "#,
+        actor_id
+    )
+    .execute(&mut *subtx)
+    .await?;
+    let watermark = sqlx::query!(
+        r#"--sql
+        SELECT rev
+        FROM follows.revs
+        WHERE actor_id = $1
+        FOR UPDATE
+        "#,
+        actor_id
+    )
+    .fetch_one(&mut *subtx)
+    .await?
+    .rev;
+
+    // If the ingester has already applied ops newer than the snapshot, edges it wrote are kept
+    // as-is and only older edges are replaced.
+    let stale = rev.map(|rev| rev < watermark.as_str()).unwrap_or(false);
+
+    let mut rkeys = Vec::with_capacity(n);
+    for (rkey, record) in records {
+        let created_at = time::OffsetDateTime::parse(
+            &record.created_at,
+            &time::format_description::well_known::Rfc3339,
+        )
+        .ok();
+
+        let subject_id = ids[&record.subject];
+        sqlx::query!(
+            r#"--sql
+            INSERT INTO follows.edges (
+                actor_id,
+                rkey,
+                subject_id,
+                created_at,
+                first_seen,
+                last_seen,
+                rev
+            )
+            VALUES ($1, $2, $3, $4, NOW(), NOW(), $5)
+            ON CONFLICT (actor_id, rkey) DO
+            UPDATE SET
+                subject_id = excluded.subject_id,
+                created_at = excluded.created_at,
+                last_seen = excluded.last_seen,
+                rev = excluded.rev
+            WHERE
+                excluded.rev IS NULL OR
+                follows.edges.rev IS NULL OR
+                follows.edges.rev <= excluded.rev
+            "#,
+            actor_id,
+            rkey,
+            subject_id,
+            created_at,
+            rev
+        )
+        .execute(&mut *subtx)
+        .await?;
+        rkeys.push(rkey);
+    }
+    sqlx::query!(
+        r#"--sql
+        WITH deleted AS (
+            DELETE FROM follows.edges
+            WHERE
+                actor_id = $1 AND
+                rkey != ALL($2) AND
+                (
+                    $4::TEXT IS NULL OR
+                    rev IS NULL OR
+                    rev <= $4
+                )
+            RETURNING *
+        )
+
+        INSERT INTO follows.edges_history (
+            actor_id,
+            rkey,
+            subject_id,
+            created_at,
+            first_seen,
+            last_seen,
+            deleted_at
+        )
+        SELECT
+            actor_id,
+            rkey,
+            subject_id,
+            created_at,
+            first_seen,
+            last_seen,
+            NOW()
+        FROM deleted
+        WHERE $3
+        "#,
+        actor_id,
+        &rkeys,
+        record_edge_history,
+        rev
+    )
+    .execute(&mut *subtx)
+    .await?;
+
+    if let Some(rev) = rev {
+        sqlx::query!(
+            r#"--sql
+            UPDATE follows.revs
+            SET rev = GREATEST(rev, $2)
+            WHERE actor_id = $1
+            "#,
+            actor_id,
+            rev
+        )
+        .execute(&mut *subtx)
+        .await?;
+    }
+
+    if stale {
+        // Follows deleted after the snapshot may have been written back, so crawl the repo again.
+        sqlx::query!(
             r#"--sql
-            INSERT INTO follows.dids (did)
+            INSERT INTO followscrawler.pending (did)
             VALUES ($1)
-            ON CONFLICT (did) DO
-            UPDATE SET did = excluded.did
-            RETURNING id
+            ON CONFLICT DO
+            NOTHING
             "#,
             did
         )
-        .fetch_one(&mut self.conn)
-        .await?
-        .id)
+        .execute(&mut *subtx)
+        .await?;
+        tracing::info!(
+            action = "requeue stale repo",
+            did = did,
+            rev = rev,
+            watermark
+        );
     }
+    subtx.commit().await?;
+    metrics::increment_counter!("skylight_followscrawler.repos");
+    metrics::counter!("skylight_followscrawler.edges_written", n as u64);
+    tracing::info!(action = "repo", did = did, n = n);
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn worker_main(
     pds_host: String,
     client: reqwest::Client,
     rl: std::sync::Arc<governor::DefaultDirectRateLimiter>,
     queued_notify: std::sync::Arc<tokio::sync::Notify>,
     mut conn: sqlx::PgConnection,
-    mut did_id_assigner: DidIdAssginer,
+    mut did_id_assigner: skylight_common::did_ids::DidIdAssigner,
+    record_edge_history: bool,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    shutdown_timeout: std::time::Duration,
 ) -> Result<(), anyhow::Error> {
     loop {
-        loop {
-            let mut tx = conn.begin().await?;
-            let did = if let Some(did) = sqlx::query!(
-                r#"--sql
-                DELETE FROM followscrawler.pending
-                WHERE
-                    did = (
-                        SELECT did
-                        FROM followscrawler.pending
-                        FOR UPDATE
-                        SKIP LOCKED
-                        LIMIT 1
-                    )
-                RETURNING did
-                "#
-            )
-            .fetch_optional(&mut *tx)
-            .await?
-            .map(|r| r.did)
-            {
-                did
+        if *shutdown.borrow() {
+            return Ok(());
+        }
+
+        let mut tx = conn.begin().await?;
+        let did = if let Some(did) = sqlx::query!(
+            r#"--sql
+            DELETE FROM followscrawler.pending
+            WHERE
+                did = (
+                    SELECT did
+                    FROM followscrawler.pending
+                    FOR UPDATE
+                    SKIP LOCKED
+                    LIMIT 1
+                )
+            RETURNING did
+            "#
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|r| r.did)
+        {
+            did
+        } else {
+            tx.rollback().await?;
+            // Repos may also be queued by other processes, e.g. the ingester requesting a resync.
+            tokio::select! {
+                _ = queued_notify.notified() => {
+                    tracing::info!("wakeup");
+                }
+                _ = tokio::time::sleep(std::time::Duration::from_secs(60)) => {}
+                _ = shutdown.wait_for(|v| *v) => {}
+            }
+            continue;
+        };
+
+        for attempt in 0..5 {
+            if attempt > 0 && *shutdown.borrow() {
+                // Don't start another attempt, leave the repo in the queue for the next run.
+                tx.rollback().await?;
+                tracing::info!(did, "rolled back crawl instead of retrying");
+                return Ok(());
+            }
+
+            let result = tokio::select! {
+                r = crawl_repo(
+                    &pds_host,
+                    &client,
+                    &rl,
+                    &mut did_id_assigner,
+                    &mut tx,
+                    &did,
+                    record_edge_history,
+                ) => Some(r),
+                _ = skylight_common::shutdown::deadline(&mut shutdown, shutdown_timeout) => None,
+            };
+
+            let result = if let Some(result) = result {
+                result
             } else {
-                queued_notify.notified().await;
-                tracing::info!("wakeup");
-                continue;
+                // Leave the repo in the queue for the next run.
+                tx.rollback().await?;
+                tracing::info!(did, "rolled back in-flight crawl");
+                return Ok(());
             };
 
-            for attempt in 0..5 {
-                if let Err(err) = {
-                    let mut blockstore_loader = atproto_repo::blockstore::Loader::new();
-                    blockstore_loader.mst_ignore_missing(true);
-
-                    let rl = &rl;
-                    let pds_host = &pds_host;
-                    let client = &client;
-                    let did_id_assigner = &mut did_id_assigner;
-                    let did = did.clone();
-                    let tx = &mut tx;
-                    (move || async move {
-                        rl.until_ready().await;
-                        let repo = tokio::time::timeout(
-                            std::time::Duration::from_secs(30 * 60),
-                            blockstore_loader.load(
-                                &mut tokio::time::timeout(
-                                    std::time::Duration::from_secs(10 * 60),
-                                    client
-                                        .get(format!(
-                                            "{}/xrpc/com.atproto.sync.getRepo?did={}",
-                                            pds_host, did
-                                        ))
-                                        .send(),
-                                )
-                                .await??
-                                .error_for_status()?
-                                .bytes_stream()
-                                .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e))
-                                .into_async_read(),
-                            ),
-                        )
-                        .await??;
-
-                        let mut records = vec![];
-                        for (key, cid) in repo.key_and_cids() {
-                            let key = String::from_utf8_lossy(key);
-                            let (collection, rkey) = match key.splitn(2, '/').collect::<Vec<_>>()[..] {
-                                [collection, rkey] => (collection, rkey),
-                                _ => {
-                                    continue;
-                                }
-                            };
-
-                            if collection != "app.bsky.graph.follow" {
-                                continue;
-                            }
-
-                            let block = if let Some(block) = repo.get_by_cid(cid) {
-                                block
-                            } else {
-                                continue;
-                            };
-
-                            #[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
-                            #[serde(rename_all = "camelCase")]
-                            struct Record {
-                                created_at: String,
-                                subject: String,
-                            }
-
-                            let record: Record =
-                                match ciborium::from_reader(std::io::Cursor::new(block)) {
-                                    Ok(record) => record,
-                                    Err(e) => {
-                                        tracing::error!(
-                                            error = format!("ciborium::from_reader: {e:?}")
-                                        );
-                                        continue;
-                                    }
-                                };
-                            records.push((rkey.to_string(), record));
-                        }
-
-                        let actor_id = did_id_assigner.assign(&did).await?;
-
-                        let n = records.len();
-                        let mut subtx = tx.begin().await?;
-                        sqlx::query!(
-                            r#"--sql
-                            DELETE FROM follows.edges
-                            WHERE actor_id = $1
-                            "#,
-                            actor_id
-                        )
-                        .execute(&mut *subtx)
-                        .await?;
-                        for (rkey, record) in records {
-                            let subject_id = did_id_assigner.assign(&record.subject).await?;
-                            sqlx::query!(
-                                r#"--sql
-                                INSERT INTO follows.edges (actor_id, rkey, subject_id)
-                                VALUES ($1, $2, $3)
-                                ON CONFLICT DO NOTHING
-                                "#,
-                                actor_id,
-                                rkey,
-                                subject_id
-                            )
-                            .execute(&mut *subtx)
-                            .await?;
-                        }
-                        subtx.commit().await?;
-                        tracing::info!(action = "repo", did = did, n = n);
-                        Ok::<_, anyhow::Error>(())
-                    })()
-                    .await
-                } {
-                    let why = format!("{:?}", err);
-                    tracing::error!(did, why, attempt);
-                    match err.downcast_ref::<atproto_repo::blockstore::Error>() {
-                        Some(atproto_repo::blockstore::Error::MissingRootCid(_)) => {
-                            // Try again.
-                            continue;
-                        }
-                        _ => {}
-                    }
-                    sqlx::query!(
-                        r#"--sql
-                        INSERT INTO followscrawler.errors (did, why)
-                        VALUES ($1, $2)
-                        "#,
-                        did,
-                        why
-                    )
-                    .execute(&mut *tx)
-                    .await?;
+            if let Err(err) = result {
+                let why = format!("{:?}", err);
+                tracing::error!(did, why, attempt);
+                metrics::increment_counter!(
+                    "skylight_followscrawler.errors",
+                    "category" => error_category(&err)
+                );
+                if let Some(atproto_repo::blockstore::Error::MissingRootCid(_)) =
+                    err.downcast_ref::<atproto_repo::blockstore::Error>()
+                {
+                    // Try again.
+                    continue;
                 }
-                break;
+                sqlx::query!(
+                    r#"--sql
+                    INSERT INTO followscrawler.errors (did, why)
+                    VALUES ($1, $2)
+                    "#,
+                    did,
+                    why
+                )
+                .execute(&mut *tx)
+                .await?;
             }
-            tx.commit().await?;
+            break;
         }
+        tx.commit().await?;
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(tracing::Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    skylight_common::logging::init();
 
     let args = Args::parse();
 
-    let conn_options = sqlx::postgres::PgConnectOptions::from_str(&args.dsn)?;
-    let mut conn = sqlx::postgres::PgConnection::connect_with(&conn_options).await?;
+    metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(args.prometheus_listen)
+        .install()?;
+
+    metrics::describe_gauge!(
+        "skylight_followscrawler.pending",
+        metrics::Unit::Count,
+        "repos waiting to be crawled"
+    );
+    metrics::describe_counter!(
+        "skylight_followscrawler.repos_listed",
+        metrics::Unit::Count,
+        "repos enqueued from listRepos"
+    );
+    metrics::describe_counter!(
+        "skylight_followscrawler.repos",
+        metrics::Unit::Count,
+        "repos crawled"
+    );
+    metrics::describe_counter!(
+        "skylight_followscrawler.bytes_downloaded",
+        metrics::Unit::Bytes,
+        "bytes downloaded"
+    );
+    metrics::describe_histogram!(
+        "skylight_followscrawler.request_duration",
+        metrics::Unit::Seconds,
+        "time until response headers or failure, by host, endpoint and status"
+    );
+    metrics::describe_counter!(
+        "skylight_followscrawler.errors",
+        metrics::Unit::Count,
+        "failed crawl attempts, by category"
+    );
+    metrics::describe_counter!(
+        "skylight_followscrawler.did_id_cache",
+        metrics::Unit::Count,
+        "DID id lookups, by whether they were cached"
+    );
+    metrics::describe_counter!(
+        "skylight_followscrawler.edges_written",
+        metrics::Unit::Count,
+        "edges written"
+    );
+    metrics::describe_histogram!(
+        "skylight_followscrawler.ratelimit_wait",
+        metrics::Unit::Seconds,
+        "time spent waiting on the rate limiter"
+    );
+
+    let (conn_options, mut conn) = skylight_common::db::connect(&args.dsn, args.migrate).await?;
 
     let rl = std::sync::Arc::new(governor::RateLimiter::direct(governor::Quota::per_second(
         std::num::NonZeroU32::new(3000 / (5 * 60)).unwrap(),
@@ -232,37 +543,62 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let queued_notify = std::sync::Arc::new(tokio::sync::Notify::new());
 
+    let mut shutdown_rx = skylight_common::shutdown::watch();
+
     let client = reqwest::Client::new();
 
+    tokio::spawn(report_pending_main(conn_options.clone()));
+
+    let did_id_cache = std::sync::Arc::new(skylight_common::did_ids::DidIdCache::new(
+        std::num::NonZeroUsize::new(DID_ID_CACHE_CAPACITY).unwrap(),
+        "skylight_followscrawler.did_id_cache",
+    ));
+
     let workers = (0..args.num_workers)
         .map(|i| {
             tokio::spawn({
                 let conn_options = conn_options.clone();
+                let did_id_cache = std::sync::Arc::clone(&did_id_cache);
                 let pds_host = args.pds_host.clone();
                 let client = client.clone();
                 let rl = std::sync::Arc::clone(&rl);
                 let queued_notify = std::sync::Arc::clone(&queued_notify);
+                let record_edge_history = args.record_edge_history;
+                let shutdown_rx = shutdown_rx.clone();
+                let shutdown_timeout = std::time::Duration::from_secs(args.shutdown_timeout);
                 async move {
                     let conn = sqlx::postgres::PgConnection::connect_with(&conn_options).await?;
-                    let did_id_assigner = DidIdAssginer {
-                        conn: sqlx::postgres::PgConnection::connect_with(&conn_options).await?,
-                    };
-                    worker_main(pds_host, client, rl, queued_notify, conn, did_id_assigner)
-                        .instrument(tracing::info_span!("worker", i))
-                        .await
+                    let did_id_assigner = skylight_common::did_ids::DidIdAssigner::new(
+                        sqlx::postgres::PgConnection::connect_with(&conn_options).await?,
+                        did_id_cache,
+                    );
+                    worker_main(
+                        pds_host,
+                        client,
+                        rl,
+                        queued_notify,
+                        conn,
+                        did_id_assigner,
+                        record_edge_history,
+                        shutdown_rx,
+                        shutdown_timeout,
+                    )
+                    .instrument(tracing::info_span!("worker", i))
+                    .await
                 }
             })
         })
         .collect::<Vec<_>>();
 
     if !args.only_crawl_queued_repos {
-        let mut cursor = sqlx::query!("SELECT cursor FROM followscrawler.cursor")
-            .fetch_optional(&mut conn)
-            .await?
-            .map(|v| v.cursor);
+        let mut cursor = skylight_common::cursors::crawler(&mut conn).await?;
+        let mut did_id_assigner = skylight_common::did_ids::DidIdAssigner::new(
+            sqlx::postgres::PgConnection::connect_with(&conn_options).await?,
+            std::sync::Arc::clone(&did_id_cache),
+        );
 
         if cursor != Some("".to_string()) {
-            loop {
+            while !*shutdown_rx.borrow() {
                 let mut url = format!(
                     "{}/xrpc/com.atproto.sync.listRepos?limit=1000",
                     args.pds_host
@@ -283,21 +619,87 @@ async fn main() -> Result<(), anyhow::Error> {
                 struct Repo {
                     did: String,
                     head: String,
+                    active: Option<bool>,
+                    status: Option<String>,
                 }
 
-                rl.until_ready().await;
-                let output: Output = serde_json::from_slice(
-                    &client
-                        .get(url)
-                        .send()
-                        .await?
-                        .error_for_status()?
-                        .bytes()
-                        .await?,
-                )?;
+                let body = tokio::select! {
+                    r = async {
+                        wait_for_rate_limit(&rl).await;
+                        get(&client, reqwest::Url::parse(&url)?, "listRepos")
+                            .await?
+                            .bytes()
+                            .await
+                            .map_err(anyhow::Error::from)
+                    } => r?,
+                    _ = shutdown_rx.wait_for(|v| *v) => {
+                        break;
+                    }
+                };
+                metrics::counter!(
+                    "skylight_followscrawler.bytes_downloaded",
+                    body.len() as u64
+                );
+                let output: Output = serde_json::from_slice(&body)?;
+                metrics::counter!(
+                    "skylight_followscrawler.repos_listed",
+                    output.repos.len() as u64
+                );
 
                 let mut tx = conn.begin().await?;
+                did_id_assigner.begin(&mut tx).await?;
+                let ids = did_id_assigner
+                    .assign_many(
+                        &output
+                            .repos
+                            .iter()
+                            .map(|repo| repo.did.as_str())
+                            .collect::<Vec<_>>(),
+                    )
+                    .await?;
+
+                // A listing isn't as of any particular point in the firehose, so its statuses are
+                // stamped -infinity: they fill in accounts the ingester hasn't seen an event for,
+                // and are replaced by the next listing, but never override an event.
+                sqlx::query!(
+                    r#"--sql
+                    INSERT INTO follows.accounts (id, active, status, updated_at)
+                    SELECT id, active, status, '-infinity'
+                    FROM
+                        UNNEST(
+                            $1::INT [], $2::BOOLEAN [], $3::TEXT []
+                        ) AS t (id, active, status)
+                    ORDER BY id
+                    ON CONFLICT (id) DO
+                    UPDATE SET
+                        active = excluded.active,
+                        status = excluded.status
+                    WHERE follows.accounts.updated_at = '-infinity'
+                    "#,
+                    &output
+                        .repos
+                        .iter()
+                        .map(|repo| ids[&repo.did])
+                        .collect::<Vec<_>>(),
+                    &output
+                        .repos
+                        .iter()
+                        .map(|repo| repo.active.unwrap_or(true))
+                        .collect::<Vec<_>>(),
+                    &output
+                        .repos
+                        .iter()
+                        .map(|repo| repo.status.clone())
+                        .collect::<Vec<_>>() as &[Option<String>],
+                )
+                .execute(&mut *tx)
+                .await?;
+
                 for repo in output.repos {
+                    if repo.active == Some(false) {
+                        // Inactive repos can't be fetched.
+                        continue;
+                    }
                     sqlx::query!(
                         r#"--sql
                         INSERT INTO followscrawler.pending (did)
@@ -313,17 +715,7 @@ async fn main() -> Result<(), anyhow::Error> {
 
                 let c = output.cursor.unwrap_or_else(|| "".to_string());
                 let done = c.is_empty();
-                sqlx::query!(
-                    r#"--sql
-                    INSERT INTO followscrawler.cursor (cursor)
-                    VALUES ($1)
-                    ON CONFLICT ((0)) DO
-                    UPDATE SET cursor = excluded.cursor
-                    "#,
-                    c
-                )
-                .execute(&mut *tx)
-                .await?;
+                skylight_common::cursors::set_crawler(&mut *tx, &c).await?;
                 cursor = Some(c);
                 tx.commit().await?;
                 queued_notify.notify_waiters();
@@ -339,6 +731,6 @@ async fn main() -> Result<(), anyhow::Error> {
         .await
         .into_iter()
         .flatten()
-        .collect::<Result<_, _>>()?;
+        .collect::<Result<(), _>>()?;
     Ok(())
 }
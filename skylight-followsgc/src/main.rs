@@ -0,0 +1,341 @@
+//! Removes DIDs from follows.dids that nothing refers to any more, and frees their ids to be
+//! assigned again, so that the INT id space doesn't run out.
+//!
+//! A deleted account's row in follows.accounts doesn't count: its edges are gone and it won't be
+//! back, so the row is removed along with the DID.
+
+use clap::Parser;
+use sqlx::Connection;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long, default_value = "postgres:///skylight")]
+    dsn: String,
+
+    /// Apply pending schema migrations before starting. Without this, the binary refuses to run
+    /// against a database whose schema isn't the version it was built against.
+    #[arg(long, default_value_t = false)]
+    migrate: bool,
+
+    /// Only report how much of the id space is used, without collecting anything.
+    #[arg(long, default_value_t = false)]
+    report: bool,
+
+    /// How many DIDs to remove per transaction. Writers are held off while each is committed.
+    #[arg(long, default_value_t = 10000)]
+    batch_size: i64,
+
+    /// How long to wait for writers to finish their transactions before giving up on a batch
+    /// and trying again later, in milliseconds.
+    #[arg(long, default_value_t = 5000)]
+    lock_timeout_ms: u64,
+
+    /// Collect again every this many seconds, instead of once.
+    #[arg(long)]
+    interval: Option<u64>,
+}
+
+/// Logs how many ids are in use, free and left to be assigned.
+async fn report(conn: &mut sqlx::postgres::PgConnection) -> Result<(), anyhow::Error> {
+    let usage = sqlx::query!(
+        r#"--sql
+        SELECT
+            (SELECT COUNT(*) FROM follows.dids) AS "used!",
+            (SELECT COUNT(*) FROM follows.free_ids) AS "free!",
+            (
+                SELECT COUNT(*)
+                FROM follows.dids AS d
+                WHERE
+                    NOT EXISTS (SELECT * FROM follows.edges AS e WHERE e.actor_id = d.id) AND
+                    NOT EXISTS (SELECT * FROM follows.edges AS e WHERE e.subject_id = d.id) AND
+                    NOT EXISTS (
+                        SELECT * FROM follows.edges_history AS e WHERE e.actor_id = d.id
+                    ) AND
+                    NOT EXISTS (
+                        SELECT * FROM follows.edges_history AS e WHERE e.subject_id = d.id
+                    ) AND
+                    NOT EXISTS (
+                        SELECT *
+                        FROM follows.accounts AS a
+                        WHERE a.id = d.id AND a.status IS DISTINCT FROM 'deleted'
+                    )
+            ) AS "orphaned!",
+            (
+                SELECT CASE WHEN is_called THEN last_value ELSE last_value - 1 END
+                FROM follows.dids_id_seq
+            ) AS "last_assigned!"
+        "#
+    )
+    .fetch_one(conn)
+    .await?;
+
+    // The sequence starts at i32::MIN and counts up.
+    let assigned = usage.last_assigned - i32::MIN as i64 + 1;
+    let unassigned = i32::MAX as i64 - usage.last_assigned;
+    tracing::info!(
+        used = usage.used,
+        free = usage.free,
+        orphaned = usage.orphaned,
+        assigned_from_sequence = assigned,
+        left_in_sequence = unassigned,
+        left_in_sequence_pct = format!("{:.2}", unassigned as f64 / (1u64 << 32) as f64 * 100.0),
+        "id space"
+    );
+    Ok(())
+}
+
+/// Whether an error is Postgres giving up on waiting for a lock.
+fn is_lock_timeout(err: &sqlx::Error) -> bool {
+    matches!(
+        err,
+        sqlx::Error::Database(err) if err.code().as_deref() == Some("55P03")
+    )
+}
+
+/// Removes one batch of orphaned DIDs with ids after `after`, returning the ids it looked at, or
+/// None if it couldn't get the lock in time.
+async fn collect_batch(
+    args: &Args,
+    conn: &mut sqlx::postgres::PgConnection,
+    after: Option<i32>,
+) -> Result<Option<Vec<i32>>, sqlx::Error> {
+    // Find candidates without holding anything up. They're checked again under the lock.
+    let candidates = sqlx::query!(
+        r#"--sql
+        SELECT d.id
+        FROM follows.dids AS d
+        WHERE
+            ($1::INT IS NULL OR d.id > $1) AND
+            NOT EXISTS (SELECT * FROM follows.edges AS e WHERE e.actor_id = d.id) AND
+            NOT EXISTS (SELECT * FROM follows.edges AS e WHERE e.subject_id = d.id) AND
+            NOT EXISTS (SELECT * FROM follows.edges_history AS e WHERE e.actor_id = d.id) AND
+            NOT EXISTS (SELECT * FROM follows.edges_history AS e WHERE e.subject_id = d.id) AND
+            NOT EXISTS (
+                SELECT *
+                FROM follows.accounts AS a
+                WHERE a.id = d.id AND a.status IS DISTINCT FROM 'deleted'
+            )
+        ORDER BY d.id
+        LIMIT $2
+        "#,
+        after,
+        args.batch_size
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(Some(candidates));
+    }
+
+    let mut tx = conn.begin().await?;
+    sqlx::query(&format!(
+        "SET LOCAL lock_timeout = {}",
+        args.lock_timeout_ms
+    ))
+    .execute(&mut *tx)
+    .await?;
+    match sqlx::query!(
+        "SELECT pg_advisory_xact_lock($1)",
+        skylight_common::did_ids::GC_LOCK
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        Ok(_) => {}
+        Err(err) if is_lock_timeout(&err) => {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(err);
+        }
+    }
+
+    // With the lock held, every writer that might have referred to these DIDs has committed,
+    // and this statement's snapshot sees what they wrote.
+    let removed = sqlx::query!(
+        r#"--sql
+        WITH
+        removed AS (
+            DELETE FROM follows.dids AS d
+            WHERE
+                d.id = ANY($1) AND
+                NOT EXISTS (SELECT * FROM follows.edges AS e WHERE e.actor_id = d.id) AND
+                NOT EXISTS (SELECT * FROM follows.edges AS e WHERE e.subject_id = d.id) AND
+                NOT EXISTS (
+                    SELECT * FROM follows.edges_history AS e WHERE e.actor_id = d.id
+                ) AND
+                NOT EXISTS (
+                    SELECT * FROM follows.edges_history AS e WHERE e.subject_id = d.id
+                ) AND
+                NOT EXISTS (
+                    SELECT *
+                    FROM follows.accounts AS a
+                    WHERE a.id = d.id AND a.status IS DISTINCT FROM 'deleted'
+                )
+            RETURNING d.id
+        ),
+
+        revs AS (
+            DELETE FROM follows.revs
+            WHERE actor_id IN (SELECT id FROM removed)
+        ),
+
+        accounts AS (
+            DELETE FROM follows.accounts
+            WHERE id IN (SELECT id FROM removed)
+        )
+
+        INSERT INTO follows.free_ids (id)
+        SELECT id FROM removed
+        "#,
+        &candidates
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    // Tell writers to drop their cached ids, since some of them may now belong to other DIDs.
+    sqlx::query!("UPDATE follows.dids_gc SET generation = generation + 1")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    tracing::info!(
+        action = "collect",
+        candidates = candidates.len(),
+        removed = removed
+    );
+    Ok(Some(candidates))
+}
+
+/// Goes through follows.dids once, removing orphaned DIDs batch by batch.
+async fn collect(
+    args: &Args,
+    conn: &mut sqlx::postgres::PgConnection,
+    shutdown_rx: &tokio::sync::watch::Receiver<bool>,
+) -> Result<(), anyhow::Error> {
+    let mut after = None;
+    while !*shutdown_rx.borrow() {
+        match collect_batch(args, conn, after).await? {
+            Some(ids) => match ids.last() {
+                Some(last) => {
+                    after = Some(*last);
+                }
+                None => {
+                    break;
+                }
+            },
+            None => {
+                tracing::info!("writers held the lock too long, trying again");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    skylight_common::logging::init();
+
+    let args = Args::parse();
+
+    let (_, mut conn) = skylight_common::db::connect(&args.dsn, args.migrate).await?;
+
+    if args.report {
+        return report(&mut conn).await;
+    }
+
+    let mut shutdown_rx = skylight_common::shutdown::watch();
+
+    loop {
+        collect(&args, &mut conn, &shutdown_rx).await?;
+        report(&mut conn).await?;
+
+        let interval = match args.interval {
+            Some(interval) => std::time::Duration::from_secs(interval),
+            None => {
+                break;
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown_rx.wait_for(|v| *v) => {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    /// Adds a DID with the given account status, as the firehose ingester records it, and returns
+    /// its id.
+    async fn add_did(
+        conn: &mut sqlx::postgres::PgConnection,
+        did: &str,
+        status: Option<&str>,
+    ) -> i32 {
+        let id = sqlx::query!(
+            "INSERT INTO follows.dids (did) VALUES ($1) RETURNING id",
+            did
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap()
+        .id;
+        sqlx::query!(
+            r#"--sql
+            INSERT INTO follows.accounts (id, active, status, updated_at)
+            VALUES ($1, $2, $3, NOW())
+            "#,
+            id,
+            status.is_none(),
+            status
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[sqlx::test(migrator = "skylight_common::schema::MIGRATOR")]
+    async fn tombstoned(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let tombstoned = add_did(&mut conn, "did:plc:tombstoned", Some("deleted")).await;
+        let deactivated = add_did(&mut conn, "did:plc:deactivated", Some("deactivated")).await;
+        let active = add_did(&mut conn, "did:plc:active", None).await;
+
+        let args = super::Args::parse_from(["skylight-followsgc"]);
+        let (_, shutdown_rx) = tokio::sync::watch::channel(false);
+        super::collect(&args, &mut conn, &shutdown_rx)
+            .await
+            .unwrap();
+
+        let free_ids = sqlx::query!("SELECT id FROM follows.free_ids")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        assert_eq!(free_ids, [tombstoned]);
+
+        let accounts = sqlx::query!("SELECT id FROM follows.accounts ORDER BY id")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        assert_eq!(accounts, [deactivated, active]);
+    }
+}
@@ -0,0 +1,72 @@
+/// Remembers the latest commit seen for each repo, so that commits which don't follow on from it
+/// can be detected. At capacity, the repo seen least recently is forgotten.
+///
+/// Changes are journaled until [`Tracker::commit`], so that when the transaction applying the
+/// commits rolls back, the tracker can be rolled back with it and a retry doesn't see its own
+/// commits as breaks in the chain.
+pub struct Tracker {
+    last: lru::LruCache<String, String>,
+    /// Each repo changed since the last commit, with its latest commit before the change.
+    journal: Vec<(String, Option<String>)>,
+}
+
+/// A point in the journal to roll back to, e.g. taken before applying a frame in a savepoint.
+#[derive(Clone, Copy)]
+pub struct Savepoint(usize);
+
+impl Tracker {
+    pub fn new(capacity: std::num::NonZeroUsize) -> Self {
+        Self {
+            last: lru::LruCache::new(capacity),
+            journal: vec![],
+        }
+    }
+
+    /// Records `this` as the latest commit of `repo`. Returns false if the commit's
+    /// predecessor is not the latest commit previously seen.
+    ///
+    /// Repos that haven't been seen yet are assumed to be intact.
+    pub fn observe(&mut self, repo: &str, prev: Option<&str>, this: &str) -> bool {
+        let last = self.last.put(repo.to_string(), this.to_string());
+        let intact = match last.as_deref() {
+            Some(last) => last == this || prev == Some(last),
+            None => true,
+        };
+        self.journal.push((repo.to_string(), last));
+        intact
+    }
+
+    /// Records `this` as the latest commit of `repo` regardless of what came before, e.g. after
+    /// the repo's state was reset.
+    pub fn reset(&mut self, repo: &str, this: &str) {
+        self.observe(repo, None, this);
+    }
+
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint(self.journal.len())
+    }
+
+    /// Undoes the changes made since `savepoint`. Repos evicted by those changes stay forgotten.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) {
+        for (repo, last) in self.journal.drain(savepoint.0..).rev() {
+            match last {
+                Some(last) => {
+                    self.last.put(repo, last);
+                }
+                None => {
+                    self.last.pop(&repo);
+                }
+            }
+        }
+    }
+
+    /// Undoes every change since the last commit.
+    pub fn rollback(&mut self) {
+        self.rollback_to(Savepoint(0));
+    }
+
+    /// Keeps the changes made so far, once the transaction they were made in has committed.
+    pub fn commit(&mut self) {
+        self.journal.clear();
+    }
+}
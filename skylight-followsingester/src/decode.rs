@@ -0,0 +1,252 @@
+/// A follow record created or deleted by a commit.
+pub enum FollowOp {
+    Create {
+        rkey: String,
+        subject: String,
+        created_at: Option<time::OffsetDateTime>,
+    },
+    Delete {
+        rkey: String,
+    },
+}
+
+/// A frame, with the follow records it carries already decoded.
+pub enum Decoded {
+    Firehose {
+        message: crate::firehose::Message,
+        follow_ops: Vec<FollowOp>,
+    },
+    Jetstream {
+        event: crate::jetstream::Event,
+        follow_ops: Vec<FollowOp>,
+    },
+}
+
+/// A frame as it was received, along with the result of decoding it.
+pub struct Frame {
+    pub raw: tokio_tungstenite::tungstenite::Message,
+    pub decoded: Result<Decoded, crate::firehose::Error>,
+}
+
+impl Decoded {
+    /// The frame's seq, or for Jetstream, its time in microseconds.
+    pub fn seq(&self) -> Option<i64> {
+        match self {
+            Decoded::Firehose { message, .. } => match message {
+                crate::firehose::Message::Account(account) => Some(account.seq),
+                crate::firehose::Message::Commit(commit) => Some(commit.seq),
+                crate::firehose::Message::Handle(handle) => Some(handle.seq),
+                crate::firehose::Message::Identity(identity) => Some(identity.seq),
+                crate::firehose::Message::Migrate(migrate) => Some(migrate.seq),
+                crate::firehose::Message::Sync(sync) => Some(sync.seq),
+                crate::firehose::Message::Tombstone(tombstone) => Some(tombstone.seq),
+                crate::firehose::Message::Info(_) | crate::firehose::Message::Unknown(_) => None,
+            },
+            Decoded::Jetstream { event, .. } => Some(event.time_us),
+        }
+    }
+
+    /// The DID of the repo the frame is about, if any.
+    pub fn repo(&self) -> Option<&str> {
+        match self {
+            Decoded::Firehose { message, .. } => match message {
+                crate::firehose::Message::Account(account) => Some(&account.did),
+                crate::firehose::Message::Commit(commit) => Some(&commit.repo),
+                crate::firehose::Message::Handle(handle) => Some(&handle.did),
+                crate::firehose::Message::Identity(identity) => Some(&identity.did),
+                crate::firehose::Message::Migrate(migrate) => Some(&migrate.did),
+                crate::firehose::Message::Sync(sync) => Some(&sync.did),
+                crate::firehose::Message::Tombstone(tombstone) => Some(&tombstone.did),
+                crate::firehose::Message::Info(_) | crate::firehose::Message::Unknown(_) => None,
+            },
+            Decoded::Jetstream { event, .. } => Some(&event.did),
+        }
+    }
+
+    /// The message type or Jetstream event kind, as a metric label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Decoded::Firehose { message, .. } => match message {
+                crate::firehose::Message::Account(_) => "#account",
+                crate::firehose::Message::Commit(_) => "#commit",
+                crate::firehose::Message::Handle(_) => "#handle",
+                crate::firehose::Message::Identity(_) => "#identity",
+                crate::firehose::Message::Info(_) => "#info",
+                crate::firehose::Message::Migrate(_) => "#migrate",
+                crate::firehose::Message::Sync(_) => "#sync",
+                crate::firehose::Message::Tombstone(_) => "#tombstone",
+                crate::firehose::Message::Unknown(_) => "unknown",
+            },
+            Decoded::Jetstream { event, .. } => match event.kind.as_str() {
+                "account" => "account",
+                "commit" => "commit",
+                "identity" => "identity",
+                _ => "unknown",
+            },
+        }
+    }
+}
+
+/// Counts a follow op that couldn't be decoded and is skipped.
+fn skip_op(reason: &'static str) {
+    metrics::increment_counter!("skylight_followsingester.skipped_ops", "reason" => reason);
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    created_at: String,
+    subject: String,
+}
+
+impl Record {
+    fn into_create(self, rkey: &str) -> FollowOp {
+        FollowOp::Create {
+            rkey: rkey.to_string(),
+            subject: self.subject,
+            created_at: time::OffsetDateTime::parse(
+                &self.created_at,
+                &time::format_description::well_known::Rfc3339,
+            )
+            .ok(),
+        }
+    }
+}
+
+/// Parses a firehose frame and decodes the follow records in it. This doesn't touch the database,
+/// so frames can be decoded in parallel ahead of being written.
+pub async fn decode(buf: &[u8]) -> Result<Decoded, crate::firehose::Error> {
+    let message = crate::firehose::Message::parse(buf)?;
+    let commit = if let crate::firehose::Message::Commit(commit) = &message {
+        commit
+    } else {
+        return Ok(Decoded::Firehose {
+            message,
+            follow_ops: vec![],
+        });
+    };
+
+    let mut follow_ops = vec![];
+    let mut blocks = None;
+    let mut blocks_failed = false;
+    for op in commit.ops.iter() {
+        let (collection, rkey) = match op.path.splitn(2, '/').collect::<Vec<_>>()[..] {
+            [collection, rkey] => (collection, rkey),
+            _ => {
+                continue;
+            }
+        };
+
+        if collection != "app.bsky.graph.follow" {
+            continue;
+        }
+
+        match op.action.as_str() {
+            "create" => {
+                if blocks_failed {
+                    skip_op("car_decode");
+                    continue;
+                }
+
+                // The commit's blocks are only parsed once, and only if they are needed.
+                if blocks.is_none() {
+                    blocks = match atproto_repo::blockstore::Loader::new()
+                        .validate_block_hash(true)
+                        .load_partial(&mut &commit.blocks[..])
+                        .await
+                    {
+                        Ok(blocks) => Some(blocks),
+                        Err(e) => {
+                            tracing::error!(
+                                seq = commit.seq,
+                                error = format!(
+                                    "atproto_repo::blockstore::Loader::load_partial: {e:?}"
+                                )
+                            );
+                            // Creates can't be read without the blocks, but deletes still apply.
+                            blocks_failed = true;
+                            skip_op("car_decode");
+                            continue;
+                        }
+                    };
+                }
+                let blocks = blocks.as_ref().unwrap();
+
+                let cid = if let Some(cid) = op.cid.clone() {
+                    cid.into()
+                } else {
+                    skip_op("missing_cid");
+                    continue;
+                };
+
+                let record: Record = match blocks.get_record(&cid) {
+                    Ok(Some(record)) => record,
+                    Ok(None) => {
+                        skip_op("missing_cid");
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            path = op.path,
+                            error = format!("ciborium::from_reader: {e:?}")
+                        );
+                        skip_op("cbor_decode");
+                        continue;
+                    }
+                };
+
+                follow_ops.push(record.into_create(rkey));
+            }
+            "delete" => {
+                follow_ops.push(FollowOp::Delete {
+                    rkey: rkey.to_string(),
+                });
+            }
+            _ => {
+                continue;
+            }
+        }
+    }
+
+    Ok(Decoded::Firehose {
+        message,
+        follow_ops,
+    })
+}
+
+/// Parses a Jetstream event and decodes the follow record in it.
+pub async fn decode_jetstream(text: &str) -> Result<Decoded, crate::firehose::Error> {
+    let event: crate::jetstream::Event = serde_json::from_str(text)?;
+
+    let mut follow_ops = vec![];
+    if let Some(commit) = event
+        .commit
+        .as_ref()
+        .filter(|commit| commit.collection == "app.bsky.graph.follow")
+    {
+        match commit.operation.as_str() {
+            "create" => match commit.record.clone().map(serde_json::from_value::<Record>) {
+                Some(Ok(record)) => follow_ops.push(record.into_create(&commit.rkey)),
+                Some(Err(e)) => {
+                    tracing::error!(
+                        did = event.did,
+                        rkey = commit.rkey,
+                        error = format!("serde_json::from_value: {e:?}")
+                    );
+                    skip_op("json_decode");
+                }
+                None => {
+                    skip_op("missing_record");
+                }
+            },
+            "delete" => {
+                follow_ops.push(FollowOp::Delete {
+                    rkey: commit.rkey.clone(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Decoded::Jetstream { event, follow_ops })
+}
@@ -1,3 +1,14 @@
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    pub active: bool,
+    pub did: String,
+    pub seq: i64,
+    pub status: Option<String>,
+    #[serde(deserialize_with = "time::serde::rfc3339::deserialize")]
+    pub time: time::OffsetDateTime,
+}
+
 #[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Commit {
@@ -7,9 +18,12 @@ pub struct Commit {
     pub commit: Option<atproto_repo::dagcbor::DagCborCid>,
     pub ops: Vec<RepoOp>,
     pub prev: Option<atproto_repo::dagcbor::DagCborCid>,
+    pub prev_data: Option<atproto_repo::dagcbor::DagCborCid>,
     pub rebase: bool,
     pub repo: String,
+    pub rev: Option<String>,
     pub seq: i64,
+    pub since: Option<String>,
     #[serde(deserialize_with = "time::serde::rfc3339::deserialize")]
     pub time: time::OffsetDateTime,
     pub too_big: bool,
@@ -25,6 +39,16 @@ pub struct Handle {
     pub time: time::OffsetDateTime,
 }
 
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    pub did: String,
+    pub handle: Option<String>,
+    pub seq: i64,
+    #[serde(deserialize_with = "time::serde::rfc3339::deserialize")]
+    pub time: time::OffsetDateTime,
+}
+
 #[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Info {
@@ -50,6 +74,18 @@ pub struct RepoOp {
     pub path: String,
 }
 
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Sync {
+    #[serde(with = "serde_bytes", default)]
+    pub blocks: Vec<u8>,
+    pub did: String,
+    pub rev: String,
+    pub seq: i64,
+    #[serde(deserialize_with = "time::serde::rfc3339::deserialize")]
+    pub time: time::OffsetDateTime,
+}
+
 #[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Tombstone {
@@ -58,12 +94,19 @@ pub struct Tombstone {
     #[serde(deserialize_with = "time::serde::rfc3339::deserialize")]
     pub time: time::OffsetDateTime,
 }
+
 pub enum Message {
+    Account(Account),
     Commit(Commit),
     Handle(Handle),
+    Identity(Identity),
     Info(Info),
     Migrate(Migrate),
+    Sync(Sync),
     Tombstone(Tombstone),
+
+    /// A message type this version doesn't know about yet.
+    Unknown(String),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -80,8 +123,11 @@ pub enum Error {
     #[error("unknown operation: {0}")]
     UnknownOperation(i8),
 
-    #[error("unknown type: {0}")]
-    UnknownType(String),
+    #[error("jetstream: {0}")]
+    Jetstream(#[from] serde_json::Error),
+
+    #[error("decoding panicked: {0}")]
+    Panic(String),
 }
 
 impl Message {
@@ -113,12 +159,15 @@ impl Message {
         }
 
         Ok(match r#type.unwrap_or_else(|| "".to_string()).as_str() {
+            "#account" => Self::Account(ciborium::from_reader(&mut cursor)?),
             "#commit" => Self::Commit(ciborium::from_reader(&mut cursor)?),
             "#handle" => Self::Handle(ciborium::from_reader(&mut cursor)?),
+            "#identity" => Self::Identity(ciborium::from_reader(&mut cursor)?),
             "#info" => Self::Info(ciborium::from_reader(&mut cursor)?),
             "#migrate" => Self::Migrate(ciborium::from_reader(&mut cursor)?),
+            "#sync" => Self::Sync(ciborium::from_reader(&mut cursor)?),
             "#tombstone" => Self::Tombstone(ciborium::from_reader(&mut cursor)?),
-            t => return Err(Error::UnknownType(t.to_string())),
+            t => Self::Unknown(t.to_string()),
         })
     }
 }
@@ -0,0 +1,68 @@
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Commit {
+    pub cid: Option<String>,
+    pub collection: String,
+    pub operation: String,
+    pub record: Option<serde_json::Value>,
+    pub rev: String,
+    pub rkey: String,
+}
+
+/// A Jetstream event. Unlike the firehose, commit events carry a single op with its record as
+/// JSON, and only for the collections that were asked for.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub account: Option<crate::firehose::Account>,
+    pub commit: Option<Commit>,
+    pub did: String,
+    pub identity: Option<crate::firehose::Identity>,
+    pub kind: String,
+    pub time_us: i64,
+}
+
+/// Applies one Jetstream event within the batch's transaction. Returns the event's time in
+/// microseconds, which is Jetstream's cursor.
+pub async fn apply_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    record_edge_history: bool,
+    event: Event,
+    follow_ops: Vec<crate::decode::FollowOp>,
+) -> Result<Option<(i64, time::OffsetDateTime)>, anyhow::Error> {
+    let time = time::OffsetDateTime::from_unix_timestamp_nanos(event.time_us as i128 * 1000)?;
+
+    match event.kind.as_str() {
+        "commit" => {
+            if let Some(commit) = event.commit {
+                crate::apply_follow_ops(
+                    tx,
+                    did_id_assigner,
+                    record_edge_history,
+                    &event.did,
+                    event.time_us,
+                    time,
+                    Some(&commit.rev),
+                    true,
+                    follow_ops,
+                )
+                .await?;
+            }
+        }
+        "account" => {
+            if let Some(account) = event.account {
+                crate::apply_account(tx, did_id_assigner, record_edge_history, &account).await?;
+            }
+        }
+        "identity" => {
+            if let Some(identity) = event.identity {
+                crate::apply_identity(tx, &identity).await?;
+            }
+        }
+        kind => {
+            tracing::warn!(kind, "skipping unknown event kind");
+        }
+    }
+
+    Ok(Some((event.time_us, time)))
+}
@@ -1,9 +1,10 @@
+mod chain;
+mod decode;
 mod firehose;
-
-use std::str::FromStr;
+mod jetstream;
 
 use clap::Parser;
-use futures::{SinkExt, StreamExt};
+use futures::{FutureExt, SinkExt, StreamExt};
 use sqlx::Connection;
 use tracing::Instrument;
 
@@ -13,271 +14,1614 @@ struct Args {
     #[arg(long, default_value = "postgres:///skylight")]
     dsn: String,
 
+    /// Apply pending schema migrations before starting. Without this, the binary refuses to run
+    /// against a database whose schema isn't the version it was built against.
+    #[arg(long, default_value_t = false)]
+    migrate: bool,
+
+    /// Relays to subscribe to. Each keeps its own cursor, and commits seen from more than one are
+    /// only applied once.
     #[arg(long, default_value = "wss://bsky.network")]
-    firehose_host: String,
+    firehose_host: Vec<String>,
+
+    /// Consume Jetstream JSON events from these hosts instead of the firehose, e.g.
+    /// wss://jetstream2.us-east.bsky.network.
+    #[arg(long)]
+    jetstream_host: Vec<String>,
 
     #[arg(long, default_value = "127.0.0.1:9000")]
     prometheus_listen: std::net::SocketAddr,
+
+    /// Move deleted edges into follows.edges_history instead of discarding them.
+    #[arg(long, default_value_t = false)]
+    record_edge_history: bool,
+
+    /// Write every frame received to rotating files in a subdirectory of this directory per host.
+    #[arg(long)]
+    record_dir: Option<std::path::PathBuf>,
+
+    /// Start a new recording file after this many bytes.
+    #[arg(long, default_value_t = 1 << 30)]
+    record_max_bytes: u64,
+
+    /// Apply the frames in these recordings, in order, instead of connecting to the network.
+    /// Cursors are left alone.
+    #[arg(long)]
+    replay: Vec<std::path::PathBuf>,
+
+    /// Apply the frames in follows.dead_letters again, removing the ones that now succeed, instead
+    /// of connecting to the network. Commits aren't checked for breaks in their repo's chain,
+    /// since they were set aside out of order.
+    #[arg(long, default_value_t = false)]
+    retry_dead_letters: bool,
+
+    /// Replay frames with the same spacing they were received with, instead of at full speed.
+    #[arg(long, default_value_t = false)]
+    replay_real_time: bool,
+
+    /// Commit after this many frames.
+    #[arg(long, default_value_t = 1000)]
+    batch_size: usize,
+
+    /// Commit a batch after this many milliseconds, even if it isn't full.
+    #[arg(long, default_value_t = 500)]
+    batch_interval_ms: u64,
+
+    /// How many frames to decode ahead of the writer.
+    #[arg(long, default_value_t = 64)]
+    decode_parallelism: usize,
+
+    /// Seconds to let an in-flight batch finish after SIGTERM before rolling it back.
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout: u64,
 }
 
-struct DidIdAssginer {
-    conn: sqlx::postgres::PgConnection,
+/// How many DIDs to cache the ids of, across all relays. The least recently used are evicted first.
+const DID_ID_CACHE_CAPACITY: usize = 1_000_000;
+
+/// How many repos to remember the latest commit of, for detecting broken commit chains.
+const CHAIN_TRACKER_CAPACITY: usize = 1_000_000;
+
+/// Advisory lock class under which writers lock the ids of the repos they are writing.
+const REPO_LOCK_CLASS: i32 = 0x736b79;
+
+const MIN_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Why a firehose subscription ended.
+enum Disconnect {
+    Shutdown,
+    Closed,
+    Error(anyhow::Error),
 }
 
-impl DidIdAssginer {
-    async fn assign(&mut self, did: &str) -> Result<i32, sqlx::Error> {
-        Ok(sqlx::query!(
-            r#"--sql
-            INSERT INTO follows.dids (did)
-            VALUES ($1)
-            ON CONFLICT (did) DO
-            UPDATE SET did = excluded.did
-            RETURNING id
-            "#,
-            did
-        )
-        .fetch_one(&mut self.conn)
-        .await?
-        .id)
+/// A host to subscribe to.
+struct Relay {
+    host: String,
+    jetstream: bool,
+}
+
+/// Whether a write failed only because it raced with another relay's writer, in which case the
+/// batch was rolled back and can be retried from the cursor.
+fn is_write_conflict(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(err)) => {
+            matches!(err.code().as_deref(), Some("40P01") | Some("40001"))
+        }
+        _ => false,
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(tracing::Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+/// Whether an error applying a frame is down to the frame itself, rather than the database being
+/// unavailable or a conflict with another relay's writer. Such frames are set aside in
+/// follows.dead_letters rather than stopping the stream.
+///
+/// Only errors from decoding the frame's contents and database errors about the values written
+/// count: data exceptions (class 22), and NOT NULL and CHECK violations. Anything else, e.g. a
+/// missing table or a foreign key violation, would hit every frame alike, so it stops the stream.
+fn is_poison(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(err)) => match err.code() {
+            Some(code) => code.starts_with("22") || code == "23502" || code == "23514",
+            None => false,
+        },
+        Some(_) => false,
+        None => true,
+    }
+}
 
-    let args = Args::parse();
+/// Sets aside a frame that couldn't be decoded or applied, to be retried with
+/// `--retry-dead-letters` once the problem is fixed.
+async fn dead_letter(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    host: Option<&str>,
+    seq: Option<i64>,
+    repo: Option<&str>,
+    raw: &tokio_tungstenite::tungstenite::Message,
+    err: &anyhow::Error,
+) -> Result<(), sqlx::Error> {
+    let jetstream = matches!(raw, tokio_tungstenite::tungstenite::Message::Text(_));
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO follows.dead_letters (host, seq, repo, jetstream, frame, error)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        host,
+        seq,
+        repo,
+        jetstream,
+        raw.clone().into_data(),
+        format!("{err:?}")
+    )
+    .execute(&mut **tx)
+    .await?;
+    metrics::increment_counter!("skylight_followsingester.dead_letters");
+    tracing::error!(
+        action = "dead letter",
+        seq = seq,
+        repo = repo,
+        error = format!("{err:?}")
+    );
+    Ok(())
+}
 
-    metrics_exporter_prometheus::PrometheusBuilder::new()
-        .with_http_listener(args.prometheus_listen)
-        .install()?;
+/// Applies dead-lettered frames again, each in its own transaction, removing the ones that now
+/// succeed. Cursors are left alone.
+///
+/// `chain_tracker` starts out empty, so each repo's first retried commit is taken to be intact.
+/// It isn't seeded from follows.revs: a frame that was set aside is behind whatever was applied
+/// since, and would always look like a break.
+///
+/// A commit that was set aside is usually skipped as stale, since later commits moved its repo's
+/// watermark past it. That's fine: setting it aside queued the repo to be crawled again.
+async fn retry_dead_letters(
+    args: &Args,
+    conn: &mut sqlx::postgres::PgConnection,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    chain_tracker: &mut chain::Tracker,
+) -> Result<(), anyhow::Error> {
+    let dead_letters = sqlx::query!(
+        r#"--sql
+        SELECT id, jetstream, frame
+        FROM follows.dead_letters
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
 
-    metrics::describe_histogram!(
-        "skylight_followsingester.ingest_delay",
-        metrics::Unit::Seconds,
-        "ingestion delay"
-    );
+    for dead_letter in dead_letters {
+        let msg = if dead_letter.jetstream {
+            tokio_tungstenite::tungstenite::Message::Text(
+                String::from_utf8_lossy(&dead_letter.frame).into_owned(),
+            )
+        } else {
+            tokio_tungstenite::tungstenite::Message::Binary(dead_letter.frame)
+        };
 
-    let conn_options = sqlx::postgres::PgConnectOptions::from_str(&args.dsn)?;
-    let mut conn = sqlx::postgres::PgConnection::connect_with(&conn_options).await?;
-    let mut did_id_assigner = DidIdAssginer {
-        conn: sqlx::postgres::PgConnection::connect_with(&conn_options).await?,
-    };
+        let mut tx = conn.begin().await?;
+        did_id_assigner.begin(&mut tx).await?;
+        let r = match decode_message(&msg).await {
+            Ok(decoded) => {
+                if let Some(repo) = decoded.repo() {
+                    let id = did_id_assigner.assign(repo).await?;
+                    sqlx::query!("SELECT pg_advisory_xact_lock($1, $2)", REPO_LOCK_CLASS, id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                apply_message(
+                    &mut tx,
+                    did_id_assigner,
+                    args.record_edge_history,
+                    chain_tracker,
+                    decoded,
+                )
+                .await
+                .map(|_| ())
+            }
+            Err(err) => Err(err.into()),
+        };
+        match r {
+            Ok(()) => {
+                sqlx::query!(
+                    "DELETE FROM follows.dead_letters WHERE id = $1",
+                    dead_letter.id
+                )
+                .execute(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                chain_tracker.commit();
+                tracing::info!(action = "retried dead letter", id = dead_letter.id);
+            }
+            Err(err) if is_poison(&err) => {
+                tx.rollback().await?;
+                chain_tracker.rollback();
+                sqlx::query!(
+                    r#"--sql
+                    UPDATE follows.dead_letters
+                    SET error = $2, ts = NOW()
+                    WHERE id = $1
+                    "#,
+                    dead_letter.id,
+                    format!("{err:?}")
+                )
+                .execute(&mut *conn)
+                .await?;
+                tracing::warn!(
+                    action = "dead letter failed again",
+                    id = dead_letter.id,
+                    error = format!("{err:?}")
+                );
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
 
-    let mut url = format!(
-        "{}/xrpc/com.atproto.sync.subscribeRepos",
-        args.firehose_host
-    );
-    if let Some(cursor) = sqlx::query!("SELECT cursor FROM follows.cursor")
-        .fetch_optional(&mut conn)
-        .await?
-        .map(|v| v.cursor)
-    {
+/// A frame being decoded, in the order it was received.
+type PendingFrame = tokio::task::JoinHandle<decode::Frame>;
+
+/// Subscribes to a relay from its persisted cursor and processes messages until the connection
+/// ends. Frames are decoded in parallel while they are written in order by a single writer.
+/// Database errors are returned as `Err`, everything else is a `Disconnect`.
+async fn subscribe(
+    args: &Args,
+    relay: &Relay,
+    conn: &mut sqlx::postgres::PgConnection,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    chain_tracker: &mut chain::Tracker,
+    recorder: Option<&mut skylight_common::recording::Recorder>,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+) -> Result<Disconnect, anyhow::Error> {
+    let mut url = if relay.jetstream {
+        format!(
+            "{}/subscribe?wantedCollections=app.bsky.graph.follow&",
+            relay.host
+        )
+    } else {
+        format!("{}/xrpc/com.atproto.sync.subscribeRepos?", relay.host)
+    };
+    if let Some(cursor) = skylight_common::cursors::relay(&mut *conn, &relay.host).await? {
         tracing::info!(cursor = cursor);
-        url.push_str(&format!("?cursor={cursor}"));
+        url.push_str(&format!("cursor={cursor}"));
     } else {
         tracing::info!("no cursor");
     }
 
-    let (stream, _) = tokio_tungstenite::connect_async(url).await?;
+    let (stream, _) = match tokio_tungstenite::connect_async(url).await {
+        Ok(v) => v,
+        Err(e) => {
+            return Ok(Disconnect::Error(e.into()));
+        }
+    };
+
+    let mut deadline_rx = shutdown_rx.clone();
+
+    let (frames_tx, frames_rx) = tokio::sync::mpsc::channel(args.decode_parallelism);
+    let reader = read_frames(stream, frames_tx, recorder, shutdown_rx);
+    let writer = write_frames(
+        frames_rx,
+        conn,
+        did_id_assigner,
+        chain_tracker,
+        Some(&relay.host),
+        args,
+    );
+    tokio::pin!(reader, writer);
+
+    let disconnect = tokio::select! {
+        disconnect = &mut reader => disconnect?,
+        r = &mut writer => {
+            return match r {
+                Ok(()) => Ok(Disconnect::Closed),
+                Err(err) if err.is::<firehose::Error>() || is_write_conflict(&err) => {
+                    Ok(Disconnect::Error(err))
+                }
+                Err(err) => Err(err),
+            };
+        }
+    };
+
+    // Frames that were already read are allowed to commit, unless that takes longer than the
+    // shutdown timeout, in which case the batch's transaction is dropped and rolled back.
+    tokio::select! {
+        r = &mut writer => {
+            match r {
+                Ok(()) => {}
+                Err(err) if err.is::<firehose::Error>() || is_write_conflict(&err) => {
+                    return Ok(Disconnect::Error(err));
+                }
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+        _ = skylight_common::shutdown::deadline(&mut deadline_rx, std::time::Duration::from_secs(args.shutdown_timeout)) => {
+            tracing::error!("timed out writing messages during shutdown");
+            return Ok(Disconnect::Shutdown);
+        }
+    }
+
+    Ok(disconnect)
+}
+
+/// Reads frames off the websocket and starts decoding each one in its own task. The channel
+/// bounds how many frames can be in flight ahead of the writer.
+async fn read_frames(
+    stream: tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >,
+    frames: tokio::sync::mpsc::Sender<PendingFrame>,
+    mut recorder: Option<&mut skylight_common::recording::Recorder>,
+    mut shutdown_rx: tokio::sync::watch::Receiver<bool>,
+) -> Result<Disconnect, skylight_common::recording::Error> {
     let (mut tx, mut rx) = stream.split();
 
-    loop {
+    let disconnect = loop {
         tokio::select! {
+            _ = shutdown_rx.wait_for(|v| *v) => {
+                let _ = tx.send(tokio_tungstenite::tungstenite::Message::Close(None)).await;
+                break Disconnect::Shutdown;
+            }
+
             _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {
-                tokio::time::timeout(
+                match tokio::time::timeout(
                     std::time::Duration::from_secs(10),
                     tx.send(tokio_tungstenite::tungstenite::Message::Ping(vec![]))
-                ).await??;
+                ).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        break Disconnect::Error(e.into());
+                    }
+                    Err(e) => {
+                        break Disconnect::Error(e.into());
+                    }
+                }
             }
 
             msg = tokio::time::timeout(std::time::Duration::from_secs(60), rx.next()) => {
-                let msg = if let Some(msg) = msg? {
-                    msg
-                } else {
-                    break;
+                let msg = match msg {
+                    Ok(Some(Ok(msg))) => msg,
+                    Ok(Some(Err(e))) => {
+                        break Disconnect::Error(e.into());
+                    }
+                    Ok(None) => {
+                        break Disconnect::Closed;
+                    }
+                    Err(e) => {
+                        break Disconnect::Error(e.into());
+                    }
                 };
 
-                let msg = if let tokio_tungstenite::tungstenite::Message::Binary(msg) = msg? {
-                    msg
+                if let Some(recorder) = recorder.as_deref_mut() {
+                    match &msg {
+                        tokio_tungstenite::tungstenite::Message::Binary(buf) => {
+                            recorder.write_binary(buf).await?;
+                        }
+                        tokio_tungstenite::tungstenite::Message::Text(text) => {
+                            recorder.write_text(text).await?;
+                        }
+                        _ => {}
+                    }
+                }
+
+                let frame = if let Some(frame) = spawn_decode(msg) {
+                    frame
                 } else {
                     continue;
                 };
 
-                process_message(&mut conn, &mut did_id_assigner, &msg)
-                    .instrument(tracing::info_span!("process_message"))
-                    .await?;
+                if frames.send(frame).await.is_err() {
+                    // The writer has stopped, and will report why.
+                    break Disconnect::Closed;
+                }
             }
         }
+    };
+
+    if let Some(recorder) = recorder {
+        recorder.flush().await?;
     }
+    Ok(disconnect)
+}
 
-    Ok(())
+/// Starts decoding a websocket message in its own task, if it is a data frame.
+fn spawn_decode(msg: tokio_tungstenite::tungstenite::Message) -> Option<PendingFrame> {
+    match &msg {
+        tokio_tungstenite::tungstenite::Message::Binary(_)
+        | tokio_tungstenite::tungstenite::Message::Text(_) => {}
+        _ => {
+            return None;
+        }
+    }
+    Some(tokio::spawn(async move {
+        // A frame that panics the decoder is dead-lettered like any other that fails to decode.
+        let decoded = match std::panic::AssertUnwindSafe(decode_message(&msg))
+            .catch_unwind()
+            .await
+        {
+            Ok(decoded) => decoded,
+            Err(panic) => Err(firehose::Error::Panic(
+                panic
+                    .downcast_ref::<&str>()
+                    .map(|v| v.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default(),
+            )),
+        };
+        decode::Frame { raw: msg, decoded }
+    }))
+}
+
+async fn decode_message(
+    msg: &tokio_tungstenite::tungstenite::Message,
+) -> Result<decode::Decoded, firehose::Error> {
+    match msg {
+        tokio_tungstenite::tungstenite::Message::Text(text) => decode::decode_jetstream(text).await,
+        msg => decode::decode(&msg.clone().into_data()).await,
+    }
 }
 
-async fn process_message(
+/// Feeds recorded frames through the writer, as if they had just been received.
+async fn replay(
+    args: &Args,
     conn: &mut sqlx::postgres::PgConnection,
-    did_id_assigner: &mut DidIdAssginer,
-    message: &[u8],
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    chain_tracker: &mut chain::Tracker,
+    mut shutdown_rx: tokio::sync::watch::Receiver<bool>,
 ) -> Result<(), anyhow::Error> {
-    let mut tx = conn.begin().await?;
-    let (seq, time) = match firehose::Message::parse(message)? {
-        firehose::Message::Info(info) => {
-            tracing::info!(name = info.name, message = info.message);
-            return Ok(());
-        }
-        firehose::Message::Commit(commit) => {
-            for op in commit.ops {
-                let (collection, rkey) = match op.path.splitn(2, '/').collect::<Vec<_>>()[..] {
-                    [collection, rkey] => (collection, rkey),
-                    _ => {
-                        continue;
+    let (frames_tx, frames_rx) = tokio::sync::mpsc::channel(args.decode_parallelism);
+
+    let reader = async move {
+        // The first frame's recorded time, and when it was replayed.
+        let mut start: Option<(i64, tokio::time::Instant)> = None;
+
+        for path in args.replay.iter() {
+            tracing::info!(action = "replay", path = ?path);
+            let mut r = tokio::io::BufReader::new(tokio::fs::File::open(path).await?);
+            while let Some((received_us, frame)) =
+                skylight_common::recording::read_frame(&mut r).await?
+            {
+                if *shutdown_rx.borrow() {
+                    return Ok(());
+                }
+
+                if args.replay_real_time {
+                    let (first_us, started) =
+                        *start.get_or_insert((received_us, tokio::time::Instant::now()));
+                    let at = started
+                        + std::time::Duration::from_micros((received_us - first_us).max(0) as u64);
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(at) => {}
+                        _ = shutdown_rx.wait_for(|v| *v) => {
+                            return Ok(());
+                        }
                     }
-                };
+                }
 
-                if collection != "app.bsky.graph.follow" {
+                let msg = match frame {
+                    skylight_common::recording::Frame::Binary(buf) => {
+                        tokio_tungstenite::tungstenite::Message::Binary(buf)
+                    }
+                    skylight_common::recording::Frame::Text(text) => {
+                        tokio_tungstenite::tungstenite::Message::Text(text)
+                    }
+                };
+                let frame = if let Some(frame) = spawn_decode(msg) {
+                    frame
+                } else {
                     continue;
+                };
+                if frames_tx.send(frame).await.is_err() {
+                    // The writer has stopped, and will report why.
+                    return Ok(());
                 }
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let writer = write_frames(frames_rx, conn, did_id_assigner, chain_tracker, None, args);
 
-                let items = match rs_car::car_read_all(&mut &commit.blocks[..], true).await {
-                    Ok((parsed, _)) => parsed
-                        .into_iter()
-                        .collect::<std::collections::HashMap<_, _>>(),
-                    Err(e) => {
-                        tracing::error!(
-                            path = op.path,
-                            error = format!("rs_car::car_read_all: {e:?}")
-                        );
-                        continue;
+    tokio::try_join!(reader, writer)?;
+    Ok(())
+}
+
+/// Locks `repos` until the end of the transaction, in order of id, so that the writers of different
+/// relays wait for each other's batches rather than deadlocking on rows they both write.
+async fn lock_repos(
+    tx: &mut sqlx::postgres::PgConnection,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    dids: &[&str],
+) -> Result<(), anyhow::Error> {
+    if dids.is_empty() {
+        return Ok(());
+    }
+    let mut ids = did_id_assigner
+        .assign_many(dids)
+        .await?
+        .into_values()
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids.dedup();
+
+    // The locks are taken in the order the subquery returns the ids.
+    sqlx::query!(
+        r#"--sql
+        SELECT pg_advisory_xact_lock($1, id)
+        FROM (SELECT id FROM UNNEST($2::INT[]) AS id ORDER BY id) AS ids
+        "#,
+        REPO_LOCK_CLASS,
+        &ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// What applying a batch's frames came to.
+#[derive(Default)]
+struct Applied {
+    /// The seq of the last frame that was applied or set aside.
+    cursor: Option<i64>,
+    /// When each applied frame was emitted by the relay.
+    times: Vec<time::OffsetDateTime>,
+    n: usize,
+    /// Why the relay ended the stream, if it did. Frames after this aren't applied.
+    ended: Option<firehose::Error>,
+}
+
+/// Applies a batch's frames in `tx`, in order, setting aside those that failed to decode.
+///
+/// With `savepoints`, each frame is applied in its own savepoint, so that one that fails with an
+/// error [`is_poison`] can be set aside too without losing the rest of the batch. Without, such an
+/// error is returned, leaving the transaction to be rolled back. Savepoints are only used to redo a
+/// batch that failed: a transaction with more than 64 of them overflows Postgres's subtransaction
+/// cache, which slows down every other session's snapshots while it's open.
+#[allow(clippy::too_many_arguments)]
+async fn apply_batch(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    chain_tracker: &mut chain::Tracker,
+    host: Option<&str>,
+    args: &Args,
+    raws: &[tokio_tungstenite::tungstenite::Message],
+    decoded: Vec<Result<decode::Decoded, firehose::Error>>,
+    savepoints: bool,
+) -> Result<Applied, anyhow::Error> {
+    let mut applied = Applied::default();
+    for (raw, decoded) in raws.iter().zip(decoded) {
+        match decoded {
+            Err(err @ firehose::Error::Firehose { .. }) => {
+                // The relay ended the stream. Commit what came before, so it isn't processed
+                // twice.
+                applied.ended = Some(err);
+                break;
+            }
+            Err(err) => {
+                dead_letter(tx, host, None, None, raw, &err.into()).await?;
+            }
+            Ok(decoded) => {
+                let kind = decoded.kind();
+                metrics::increment_counter!(
+                    "skylight_followsingester.events",
+                    "type" => kind,
+                    "host" => host.unwrap_or("replay").to_string()
+                );
+                let seq = decoded.seq();
+                let repo = decoded.repo().map(|repo| repo.to_string());
+
+                let r = if savepoints {
+                    let mut savepoint = tx.begin().await?;
+                    let chain_savepoint = chain_tracker.savepoint();
+                    let r = apply_message(
+                        &mut savepoint,
+                        did_id_assigner,
+                        args.record_edge_history,
+                        chain_tracker,
+                        decoded,
+                    )
+                    .instrument(tracing::info_span!("apply_message"))
+                    .await;
+                    if r.is_ok() {
+                        savepoint.commit().await?;
+                    } else {
+                        savepoint.rollback().await?;
+                        chain_tracker.rollback_to(chain_savepoint);
                     }
+                    r
+                } else {
+                    apply_message(
+                        tx,
+                        did_id_assigner,
+                        args.record_edge_history,
+                        chain_tracker,
+                        decoded,
+                    )
+                    .instrument(tracing::info_span!("apply_message"))
+                    .await
                 };
-
-                match op.action.as_str() {
-                    "create" => {
-                        let item = if let Some(item) = op.cid.and_then(|cid| items.get(&cid.into()))
-                        {
-                            item
-                        } else {
-                            continue;
-                        };
-
-                        #[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
-                        #[serde(rename_all = "camelCase")]
-                        struct Record {
-                            created_at: String,
-                            subject: String,
+                match r {
+                    Ok(Some((seq, time))) => {
+                        applied.cursor = Some(seq);
+                        applied.times.push(time);
+                    }
+                    Ok(None) => {}
+                    Err(err) if savepoints && is_poison(&err) => {
+                        dead_letter(tx, host, seq, repo.as_deref(), raw, &err).await?;
+                        if let (Some(repo), Some(seq)) = (&repo, seq) {
+                            if matches!(kind, "#commit" | "commit") {
+                                // The commit's rev was rolled back with it, so later commits
+                                // move the repo's watermark past it, and retrying it would skip
+                                // it as stale. Crawl the repo again instead.
+                                request_resync(tx, repo, "dead letter", seq).await?;
+                            }
+                        }
+                        if seq.is_some() {
+                            applied.cursor = seq;
                         }
+                    }
+                    Err(err) => {
+                        return Err(err);
+                    }
+                }
+            }
+        }
 
-                        let record: Record = match ciborium::from_reader(std::io::Cursor::new(item))
-                        {
-                            Ok(record) => record,
-                            Err(e) => {
-                                tracing::error!(
-                                    path = op.path,
-                                    error = format!("ciborium::from_reader: {e:?}")
-                                );
-                                continue;
-                            }
-                        };
-
-                        let actor_id = did_id_assigner.assign(&commit.repo).await?;
-                        let subject_id = did_id_assigner.assign(&record.subject).await?;
-                        sqlx::query!(
-                            r#"--sql
-                            INSERT INTO follows.edges (actor_id, rkey, subject_id)
-                            VALUES ($1, $2, $3)
-                            ON CONFLICT DO NOTHING
-                            "#,
-                            actor_id,
-                            rkey,
-                            subject_id
-                        )
-                        .execute(&mut *tx)
-                        .await?;
+        applied.n += 1;
+    }
+    Ok(applied)
+}
+
+/// Applies decoded frames in the order they were received. Frames are committed in batches of up
+/// to `--batch-size` frames or `--batch-interval-ms`, whichever comes first, together with the
+/// host's cursor as of the last frame in the batch, so a restart resumes exactly after the last
+/// committed frame.
+///
+/// A batch's frames are collected before any are applied, so that the repos they write can be
+/// locked up front with [`lock_repos`]. If applying one of them fails in a way that is down to the
+/// frame, the batch is rolled back and redone frame by frame, setting that one aside.
+async fn write_frames(
+    mut frames: tokio::sync::mpsc::Receiver<PendingFrame>,
+    conn: &mut sqlx::postgres::PgConnection,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    chain_tracker: &mut chain::Tracker,
+    host: Option<&str>,
+    args: &Args,
+) -> Result<(), anyhow::Error> {
+    let batch_interval = std::time::Duration::from_millis(args.batch_interval_ms);
+
+    loop {
+        let mut next = frames.recv().await;
+        if next.is_none() {
+            return Ok(());
+        }
+        let deadline = tokio::time::Instant::now() + batch_interval;
+
+        let mut batch = vec![];
+        while let Some(frame) = next.take() {
+            batch.push(frame.await?);
+            if batch.len() >= args.batch_size {
+                break;
+            }
+            next = tokio::select! {
+                frame = frames.recv() => frame,
+                _ = tokio::time::sleep_until(deadline) => None,
+            };
+        }
+
+        // Undo what a batch that failed before committing recorded.
+        chain_tracker.rollback();
 
-                        tracing::info!(
-                            action = "create follow",
-                            seq = commit.seq,
-                            actor_did = commit.repo,
-                            subject_did = record.subject,
-                            rkey = rkey,
-                        )
+        let repos = batch
+            .iter()
+            .filter_map(|frame| Some(frame.decoded.as_ref().ok()?.repo()?.to_string()))
+            .collect::<Vec<_>>();
+        let repos = repos.iter().map(|repo| repo.as_str()).collect::<Vec<_>>();
+
+        let started = std::time::Instant::now();
+        let mut tx = conn.begin().await?;
+        did_id_assigner.begin(&mut tx).await?;
+        lock_repos(&mut tx, did_id_assigner, &repos).await?;
+        let (raws, decoded): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|frame| (frame.raw, frame.decoded))
+            .unzip();
+        let applied = match apply_batch(
+            &mut tx,
+            did_id_assigner,
+            chain_tracker,
+            host,
+            args,
+            &raws,
+            decoded,
+            false,
+        )
+        .await
+        {
+            Ok(applied) => applied,
+            Err(err) if is_poison(&err) => {
+                tracing::warn!(
+                    action = "redo batch",
+                    frames = raws.len(),
+                    error = format!("{err:?}")
+                );
+                tx.rollback().await?;
+                chain_tracker.rollback();
+
+                // The decoded frames were used up, so decode them again.
+                let mut decoded = Vec::with_capacity(raws.len());
+                for raw in &raws {
+                    if let Some(frame) = spawn_decode(raw.clone()) {
+                        decoded.push(frame.await?.decoded);
                     }
-                    "delete" => {
-                        sqlx::query!(
-                            r#"--sql
-                            WITH ids AS (
-                                SELECT id
-                                FROM follows.dids
-                                WHERE did = $1
-                            )
-                            DELETE FROM follows.edges
-                            WHERE
-                                actor_id IN (SELECT id FROM ids) AND
-                                rkey = $2
-                            "#,
-                            commit.repo,
-                            rkey,
-                        )
-                        .execute(&mut *tx)
-                        .await?;
+                }
+
+                tx = conn.begin().await?;
+                did_id_assigner.begin(&mut tx).await?;
+                lock_repos(&mut tx, did_id_assigner, &repos).await?;
+                apply_batch(
+                    &mut tx,
+                    did_id_assigner,
+                    chain_tracker,
+                    host,
+                    args,
+                    &raws,
+                    decoded,
+                    true,
+                )
+                .await?
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        let Applied {
+            cursor,
+            times,
+            n,
+            ended,
+        } = applied;
+
+        if let (Some(host), Some(cursor)) = (host, cursor) {
+            skylight_common::cursors::set_relay(&mut *tx, host, cursor).await?;
+        }
+        let commit_started = std::time::Instant::now();
+        tx.commit().await?;
+        chain_tracker.commit();
+        metrics::histogram!(
+            "skylight_followsingester.commit_duration",
+            commit_started.elapsed()
+        );
+        metrics::histogram!(
+            "skylight_followsingester.transaction_duration",
+            started.elapsed()
+        );
 
-                        tracing::info!(
-                            action = "delete follow",
-                            seq = commit.seq,
-                            actor_did = commit.repo,
-                            rkey = rkey,
+        let now = time::OffsetDateTime::now_utc();
+        if let (Some(host), Some(cursor), Some(time)) = (host, cursor, times.last()) {
+            metrics::gauge!(
+                "skylight_followsingester.cursor",
+                cursor as f64,
+                "host" => host.to_string()
+            );
+            metrics::gauge!(
+                "skylight_followsingester.head_lag",
+                (now - *time).as_seconds_f64(),
+                "host" => host.to_string()
+            );
+        }
+        for time in times {
+            metrics::histogram!(
+                "skylight_followsingester.ingest_delay",
+                (now - time).as_seconds_f64()
+            );
+        }
+        metrics::histogram!("skylight_followsingester.batch_size", n as f64);
+
+        if let Some(err) = ended {
+            return Err(err.into());
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    skylight_common::logging::init();
+
+    let args = Args::parse();
+
+    metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(args.prometheus_listen)
+        .install()?;
+
+    metrics::describe_histogram!(
+        "skylight_followsingester.ingest_delay",
+        metrics::Unit::Seconds,
+        "ingestion delay"
+    );
+    metrics::describe_histogram!(
+        "skylight_followsingester.batch_size",
+        metrics::Unit::Count,
+        "frames committed per transaction"
+    );
+    metrics::describe_histogram!(
+        "skylight_followsingester.transaction_duration",
+        metrics::Unit::Seconds,
+        "time from beginning a batch's transaction to committing it"
+    );
+    metrics::describe_histogram!(
+        "skylight_followsingester.commit_duration",
+        metrics::Unit::Seconds,
+        "time taken to commit a batch's transaction"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.events",
+        metrics::Unit::Count,
+        "frames applied, by message type"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.follow_ops",
+        metrics::Unit::Count,
+        "follow records written, by action"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.skipped_ops",
+        metrics::Unit::Count,
+        "follow ops that couldn't be decoded, by reason"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.stale_commits",
+        metrics::Unit::Count,
+        "commits skipped because the repo was already at or past their rev"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.did_id_cache",
+        metrics::Unit::Count,
+        "DID id lookups, by whether they were cached"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.dead_letters",
+        metrics::Unit::Count,
+        "frames set aside because they couldn't be decoded or applied"
+    );
+    metrics::describe_gauge!(
+        "skylight_followsingester.cursor",
+        metrics::Unit::Count,
+        "last committed seq, by host"
+    );
+    metrics::describe_gauge!(
+        "skylight_followsingester.head_lag",
+        metrics::Unit::Seconds,
+        "age of the last committed event, i.e. how far behind the relay's head we are, by host"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.reconnects",
+        metrics::Unit::Count,
+        "firehose reconnects, by reason"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.resyncs",
+        metrics::Unit::Count,
+        "repos queued for a full crawl, by reason"
+    );
+    metrics::describe_counter!(
+        "skylight_followsingester.gaps",
+        metrics::Unit::Count,
+        "known gaps in the consumed stream, by reason"
+    );
+
+    let (conn_options, mut conn) = skylight_common::db::connect(&args.dsn, args.migrate).await?;
+
+    let shutdown_rx = skylight_common::shutdown::watch();
+
+    let did_id_cache = std::sync::Arc::new(skylight_common::did_ids::DidIdCache::new(
+        std::num::NonZeroUsize::new(DID_ID_CACHE_CAPACITY).unwrap(),
+        "skylight_followsingester.did_id_cache",
+    ));
+
+    if args.retry_dead_letters {
+        let mut conn = sqlx::postgres::PgConnection::connect_with(&conn_options).await?;
+        let mut did_id_assigner = skylight_common::did_ids::DidIdAssigner::new(
+            sqlx::postgres::PgConnection::connect_with(&conn_options).await?,
+            did_id_cache,
+        );
+        let mut chain_tracker =
+            chain::Tracker::new(std::num::NonZeroUsize::new(CHAIN_TRACKER_CAPACITY).unwrap());
+        return retry_dead_letters(&args, &mut conn, &mut did_id_assigner, &mut chain_tracker)
+            .await;
+    }
+
+    if !args.replay.is_empty() {
+        let mut conn = sqlx::postgres::PgConnection::connect_with(&conn_options).await?;
+        let mut did_id_assigner = skylight_common::did_ids::DidIdAssigner::new(
+            sqlx::postgres::PgConnection::connect_with(&conn_options).await?,
+            did_id_cache,
+        );
+        let mut chain_tracker =
+            chain::Tracker::new(std::num::NonZeroUsize::new(CHAIN_TRACKER_CAPACITY).unwrap());
+        return replay(
+            &args,
+            &mut conn,
+            &mut did_id_assigner,
+            &mut chain_tracker,
+            shutdown_rx,
+        )
+        .await;
+    }
+
+    let relays = if args.jetstream_host.is_empty() {
+        args.firehose_host
+            .iter()
+            .map(|host| Relay {
+                host: host.clone(),
+                jetstream: false,
+            })
+            .collect::<Vec<_>>()
+    } else {
+        args.jetstream_host
+            .iter()
+            .map(|host| Relay {
+                host: host.clone(),
+                jetstream: true,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The cursor from before cursors were kept per host was most likely for the first host.
+    if let Some(relay) = relays.first() {
+        if let Some(cursor) =
+            skylight_common::cursors::adopt_legacy_relay(&mut conn, &relay.host, relay.jetstream)
+                .await?
+        {
+            tracing::info!(
+                action = "adopt cursor",
+                host = relay.host,
+                cursor = cursor,
+                "carried over the cursor from before cursors were kept per host"
+            );
+        }
+    }
+    drop(conn);
+
+    futures::future::try_join_all(relays.iter().map(|relay| {
+        relay_main(
+            &args,
+            relay,
+            &conn_options,
+            std::sync::Arc::clone(&did_id_cache),
+            shutdown_rx.clone(),
+        )
+        .instrument(tracing::info_span!("relay", host = relay.host))
+    }))
+    .await?;
+
+    Ok(())
+}
+
+/// Keeps a subscription to one relay going, reconnecting with backoff, until shutdown.
+async fn relay_main(
+    args: &Args,
+    relay: &Relay,
+    conn_options: &sqlx::postgres::PgConnectOptions,
+    did_id_cache: std::sync::Arc<skylight_common::did_ids::DidIdCache>,
+    mut shutdown_rx: tokio::sync::watch::Receiver<bool>,
+) -> Result<(), anyhow::Error> {
+    let mut conn = sqlx::postgres::PgConnection::connect_with(conn_options).await?;
+    let mut did_id_assigner = skylight_common::did_ids::DidIdAssigner::new(
+        sqlx::postgres::PgConnection::connect_with(conn_options).await?,
+        did_id_cache,
+    );
+
+    // Each relay's stream is checked for gaps on its own.
+    let mut chain_tracker =
+        chain::Tracker::new(std::num::NonZeroUsize::new(CHAIN_TRACKER_CAPACITY).unwrap());
+
+    let mut recorder = match &args.record_dir {
+        Some(dir) => {
+            let dir = dir.join(
+                relay
+                    .host
+                    .replace(|c: char| !c.is_ascii_alphanumeric() && c != '.', "_"),
+            );
+            tokio::fs::create_dir_all(&dir).await?;
+            Some(skylight_common::recording::Recorder::new(
+                dir,
+                args.record_max_bytes,
+            ))
+        }
+        None => None,
+    };
+
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let started = std::time::Instant::now();
+        let reason = match subscribe(
+            args,
+            relay,
+            &mut conn,
+            &mut did_id_assigner,
+            &mut chain_tracker,
+            recorder.as_mut(),
+            shutdown_rx.clone(),
+        )
+        .await?
+        {
+            Disconnect::Shutdown => {
+                break;
+            }
+            Disconnect::Closed => {
+                tracing::warn!("firehose closed");
+                "closed"
+            }
+            Disconnect::Error(err) => {
+                tracing::error!(error = format!("{err:?}"), "firehose failed");
+                if let Some(firehose::Error::Firehose { error, .. }) =
+                    err.downcast_ref::<firehose::Error>()
+                {
+                    if error == "FutureCursor" {
+                        // The relay doesn't know about our cursor, e.g. because its sequence was
+                        // reset. Start again from the live stream.
+                        metrics::increment_counter!(
+                            "skylight_followsingester.gaps",
+                            "reason" => "future_cursor",
+                            "host" => relay.host.clone()
                         );
-                    }
-                    _ => {
-                        continue;
+                        skylight_common::cursors::clear_relay(&mut conn, &relay.host).await?;
                     }
                 }
+                "error"
             }
-            (commit.seq, commit.time)
+        };
+        metrics::increment_counter!(
+            "skylight_followsingester.reconnects",
+            "reason" => reason,
+            "host" => relay.host.clone()
+        );
+
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
         }
-        firehose::Message::Tombstone(tombstone) => {
-            sqlx::query!(
-                r#"--sql
-                WITH ids AS (
-                    SELECT id
-                    FROM follows.dids
-                    WHERE did = $1
-                )
-                DELETE FROM follows.edges
-                WHERE
-                    actor_id IN (SELECT id FROM ids) OR
-                    subject_id IN (SELECT id FROM ids)
-                "#,
-                tombstone.did
+        tracing::info!(backoff = ?backoff, "reconnecting");
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown_rx.wait_for(|v| *v) => {
+                break;
+            }
+        }
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+
+    Ok(())
+}
+
+/// Records an account's hosting status. Edges of inactive accounts are hidden by
+/// follows.visible_edges until the account becomes active again.
+async fn set_account_status(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    id: i32,
+    active: bool,
+    status: Option<&str>,
+    time: time::OffsetDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO follows.accounts (id, active, status, updated_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (id) DO
+        UPDATE SET
+            active = excluded.active,
+            status = excluded.status,
+            updated_at = excluded.updated_at
+        WHERE follows.accounts.updated_at <= excluded.updated_at
+        "#,
+        id,
+        active,
+        status,
+        time
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Queues a repo to be crawled again by the crawler.
+async fn request_resync(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did: &str,
+    reason: &str,
+    seq: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO followscrawler.resyncs (did, reason, seq)
+        VALUES ($1, $2, $3)
+        "#,
+        did,
+        reason,
+        seq
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO followscrawler.pending (did)
+        VALUES ($1)
+        ON CONFLICT DO
+        NOTHING
+        "#,
+        did
+    )
+    .execute(&mut **tx)
+    .await?;
+    metrics::increment_counter!("skylight_followsingester.resyncs", "reason" => reason.to_string());
+    tracing::info!(action = "resync", did = did, reason = reason, seq = seq);
+    Ok(())
+}
+
+/// Advances a repo's rev watermark, returning false if it is already at or past `rev`. This is
+/// what deduplicates commits seen from more than one relay, and skips commits already covered by a
+/// crawl. With `allow_same_rev`, a commit at the watermark is let through again, since Jetstream
+/// delivers each op of a commit as a separate event with the same rev.
+async fn advance_rev(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    actor_id: i32,
+    rev: &str,
+    allow_same_rev: bool,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"--sql
+        INSERT INTO follows.revs (actor_id, rev)
+        VALUES ($1, $2)
+        ON CONFLICT (actor_id) DO
+        UPDATE SET rev = excluded.rev
+        WHERE
+            follows.revs.rev < excluded.rev OR
+            ($3 AND follows.revs.rev = excluded.rev)
+        RETURNING actor_id
+        "#,
+        actor_id,
+        rev,
+        allow_same_rev
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .is_some())
+}
+
+/// Removes every edge to or from a deleted account.
+async fn delete_account_edges(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did: &str,
+    time: time::OffsetDateTime,
+    record_edge_history: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"--sql
+        WITH
+        ids AS (
+            SELECT id
+            FROM follows.dids
+            WHERE did = $1
+        ),
+
+        deleted AS (
+            DELETE FROM follows.edges
+            WHERE
+                actor_id IN (SELECT id FROM ids) OR
+                subject_id IN (SELECT id FROM ids)
+            RETURNING *
+        )
+
+        INSERT INTO follows.edges_history (
+            actor_id,
+            rkey,
+            subject_id,
+            created_at,
+            first_seen,
+            last_seen,
+            deleted_at
+        )
+        SELECT
+            actor_id,
+            rkey,
+            subject_id,
+            created_at,
+            first_seen,
+            last_seen,
+            $2
+        FROM deleted
+        WHERE $3
+        "#,
+        did,
+        time,
+        record_edge_history,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Applies one decoded frame within the batch's transaction. Returns the frame's cursor and time
+/// if it advances the cursor.
+async fn apply_message(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    record_edge_history: bool,
+    chain_tracker: &mut chain::Tracker,
+    decoded: decode::Decoded,
+) -> Result<Option<(i64, time::OffsetDateTime)>, anyhow::Error> {
+    match decoded {
+        decode::Decoded::Firehose {
+            message,
+            follow_ops,
+        } => {
+            apply_firehose_message(
+                tx,
+                did_id_assigner,
+                record_edge_history,
+                chain_tracker,
+                message,
+                follow_ops,
+            )
+            .await
+        }
+        decode::Decoded::Jetstream { event, follow_ops } => {
+            jetstream::apply_event(tx, did_id_assigner, record_edge_history, event, follow_ops)
+                .await
+        }
+    }
+}
+
+async fn apply_firehose_message(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    record_edge_history: bool,
+    chain_tracker: &mut chain::Tracker,
+    message: firehose::Message,
+    follow_ops: Vec<decode::FollowOp>,
+) -> Result<Option<(i64, time::OffsetDateTime)>, anyhow::Error> {
+    Ok(Some(match message {
+        firehose::Message::Info(info) => {
+            tracing::info!(name = info.name, message = info.message);
+            if info.name == "OutdatedCursor" {
+                // The relay no longer has events from our cursor and will resume from the oldest
+                // one it has.
+                metrics::increment_counter!(
+                    "skylight_followsingester.gaps",
+                    "reason" => "outdated_cursor"
+                );
+            }
+            return Ok(None);
+        }
+        firehose::Message::Commit(commit) => {
+            let link = match (&commit.rev, &commit.commit) {
+                (Some(rev), _) => Some((commit.since.clone(), rev.clone())),
+                (None, Some(cid)) => Some((
+                    commit
+                        .prev
+                        .clone()
+                        .map(|prev| cid::Cid::from(prev).to_string()),
+                    cid::Cid::from(cid.clone()).to_string(),
+                )),
+                (None, None) => None,
+            };
+            let chain_intact = match link {
+                Some((prev, this)) => chain_tracker.observe(&commit.repo, prev.as_deref(), &this),
+                None => true,
+            };
+
+            // Ops in these commits may be missing or incomplete, so the whole repo needs to be
+            // crawled again.
+            let resync_reason = if commit.too_big {
+                Some("too_big")
+            } else if commit.rebase {
+                Some("rebase")
+            } else if !chain_intact {
+                Some("chain_break")
+            } else {
+                None
+            };
+            if let Some(reason) = resync_reason {
+                request_resync(tx, &commit.repo, reason, commit.seq).await?;
+            }
+
+            apply_follow_ops(
+                tx,
+                did_id_assigner,
+                record_edge_history,
+                &commit.repo,
+                commit.seq,
+                commit.time,
+                commit.rev.as_deref(),
+                false,
+                follow_ops,
             )
-            .execute(&mut *tx)
             .await?;
+            (commit.seq, commit.time)
+        }
+        firehose::Message::Tombstone(tombstone) => {
+            let id = did_id_assigner.assign(&tombstone.did).await?;
+            set_account_status(tx, id, false, Some("deleted"), tombstone.time).await?;
+            delete_account_edges(tx, &tombstone.did, tombstone.time, record_edge_history).await?;
             (tombstone.seq, tombstone.time)
         }
-        firehose::Message::Handle(handle) => (handle.seq, handle.time),
+        firehose::Message::Account(account) => {
+            apply_account(tx, did_id_assigner, record_edge_history, &account).await?;
+            (account.seq, account.time)
+        }
+        firehose::Message::Identity(identity) => {
+            apply_identity(tx, &identity).await?;
+            (identity.seq, identity.time)
+        }
+        firehose::Message::Sync(sync) => {
+            // The repo's state was reset to this commit, so the ops since the last one we saw
+            // won't be sent.
+            chain_tracker.reset(&sync.did, &sync.rev);
+            request_resync(tx, &sync.did, "sync", sync.seq).await?;
+            (sync.seq, sync.time)
+        }
+        firehose::Message::Unknown(r#type) => {
+            tracing::warn!(r#type, "skipping unknown message type");
+            return Ok(None);
+        }
+        firehose::Message::Handle(handle) => {
+            set_handle(tx, &handle.did, &handle.handle, handle.time).await?;
+            tracing::info!(
+                action = "handle",
+                seq = handle.seq,
+                did = handle.did,
+                handle = handle.handle,
+            );
+            (handle.seq, handle.time)
+        }
         firehose::Message::Migrate(migrate) => (migrate.seq, migrate.time),
+    }))
+}
+
+/// Applies the follow ops of one commit, unless the repo's rev watermark is already past it, e.g.
+/// because another relay delivered it first.
+#[allow(clippy::too_many_arguments)]
+async fn apply_follow_ops(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    record_edge_history: bool,
+    repo: &str,
+    seq: i64,
+    time: time::OffsetDateTime,
+    rev: Option<&str>,
+    allow_same_rev: bool,
+    follow_ops: Vec<decode::FollowOp>,
+) -> Result<(), anyhow::Error> {
+    // Look up the ids of the repo and every subject at once.
+    let mut dids = vec![repo];
+    for op in &follow_ops {
+        if let decode::FollowOp::Create { subject, .. } = op {
+            dids.push(subject);
+        }
+    }
+    let ids = if follow_ops.is_empty() {
+        std::collections::HashMap::new()
+    } else {
+        did_id_assigner.assign_many(&dids).await?
+    };
+
+    let ops = match rev {
+        Some(rev) if !follow_ops.is_empty() => {
+            let actor_id = ids[repo];
+            if advance_rev(tx, actor_id, rev, allow_same_rev).await? {
+                follow_ops
+            } else {
+                // Another relay has already delivered this commit, or the crawler has written a
+                // snapshot at or after it.
+                metrics::increment_counter!("skylight_followsingester.stale_commits");
+                tracing::info!(
+                    action = "skip stale commit",
+                    seq = seq,
+                    actor_did = repo,
+                    rev = rev,
+                );
+                vec![]
+            }
+        }
+        _ => follow_ops,
     };
+
+    for op in ops {
+        match op {
+            decode::FollowOp::Create {
+                rkey,
+                subject,
+                created_at,
+            } => {
+                let actor_id = ids[repo];
+                let subject_id = ids[&subject];
+                sqlx::query!(
+                    r#"--sql
+                    INSERT INTO follows.edges (
+                        actor_id,
+                        rkey,
+                        subject_id,
+                        created_at,
+                        first_seen,
+                        last_seen,
+                        rev
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $5, $6)
+                    ON CONFLICT (actor_id, rkey) DO
+                    UPDATE SET
+                        last_seen = excluded.last_seen,
+                        rev = excluded.rev
+                    "#,
+                    actor_id,
+                    rkey,
+                    subject_id,
+                    created_at,
+                    time,
+                    rev
+                )
+                .execute(&mut **tx)
+                .await?;
+
+                metrics::increment_counter!(
+                    "skylight_followsingester.follow_ops",
+                    "action" => "create"
+                );
+                tracing::info!(
+                    action = "create follow",
+                    seq = seq,
+                    actor_did = repo,
+                    subject_did = subject,
+                    rkey = rkey,
+                )
+            }
+            decode::FollowOp::Delete { rkey } => {
+                sqlx::query!(
+                    r#"--sql
+                    WITH
+                    ids AS (
+                        SELECT id
+                        FROM follows.dids
+                        WHERE did = $1
+                    ),
+
+                    deleted AS (
+                        DELETE FROM follows.edges
+                        WHERE
+                            actor_id IN (SELECT id FROM ids) AND
+                            rkey = $2
+                        RETURNING *
+                    )
+
+                    INSERT INTO follows.edges_history (
+                        actor_id,
+                        rkey,
+                        subject_id,
+                        created_at,
+                        first_seen,
+                        last_seen,
+                        deleted_at
+                    )
+                    SELECT
+                        actor_id,
+                        rkey,
+                        subject_id,
+                        created_at,
+                        first_seen,
+                        last_seen,
+                        $3
+                    FROM deleted
+                    WHERE $4
+                    "#,
+                    repo,
+                    rkey,
+                    time,
+                    record_edge_history,
+                )
+                .execute(&mut **tx)
+                .await?;
+
+                metrics::increment_counter!(
+                    "skylight_followsingester.follow_ops",
+                    "action" => "delete"
+                );
+                tracing::info!(
+                    action = "delete follow",
+                    seq = seq,
+                    actor_did = repo,
+                    rkey = rkey,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records an account status change, deleting the account's edges if it was deleted.
+async fn apply_account(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did_id_assigner: &mut skylight_common::did_ids::DidIdAssigner,
+    record_edge_history: bool,
+    account: &firehose::Account,
+) -> Result<(), anyhow::Error> {
+    let id = did_id_assigner.assign(&account.did).await?;
+    set_account_status(
+        tx,
+        id,
+        account.active,
+        account.status.as_deref(),
+        account.time,
+    )
+    .await?;
+    if account.status.as_deref() == Some("deleted") {
+        delete_account_edges(tx, &account.did, account.time, record_edge_history).await?;
+    }
+    tracing::info!(
+        action = "account",
+        seq = account.seq,
+        did = account.did,
+        active = account.active,
+        status = account.status,
+    );
+    Ok(())
+}
+
+/// Records an account's current handle, unless a later one has already been recorded. The relay
+/// sends `handle.invalid` for accounts whose handle doesn't resolve back to them, which is
+/// recorded as having no handle.
+async fn set_handle(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    did: &str,
+    handle: &str,
+    time: time::OffsetDateTime,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"--sql
-        INSERT INTO follows.cursor (cursor)
-        VALUES ($1)
-        ON CONFLICT ((0)) DO
-        UPDATE SET cursor = excluded.cursor
+        INSERT INTO plc.handles (did, handle, updated_at)
+        VALUES ($1, NULLIF(LOWER($2), 'handle.invalid'), $3)
+        ON CONFLICT (did) DO
+        UPDATE SET
+            handle = excluded.handle,
+            updated_at = excluded.updated_at
+        WHERE plc.handles.updated_at <= excluded.updated_at
         "#,
-        seq
+        did,
+        handle,
+        time
     )
-    .execute(&mut *tx)
+    .execute(&mut **tx)
     .await?;
+    Ok(())
+}
 
-    let now = time::OffsetDateTime::now_utc();
-    metrics::histogram!(
-        "skylight_followsingester.ingest_delay",
-        (now - time).as_seconds_f64()
+/// Records the account's handle, if the event has one, and queues its DID, whose document may also
+/// have changed, e.g. a new PDS, for the PLC ingester to refresh. An event without a handle leaves
+/// the recorded one as it is.
+async fn apply_identity(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    identity: &firehose::Identity,
+) -> Result<(), anyhow::Error> {
+    if let Some(handle) = &identity.handle {
+        set_handle(tx, &identity.did, handle, identity.time).await?;
+    }
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO plc.refresh_queue (did)
+        VALUES ($1)
+        ON CONFLICT (did) DO
+        UPDATE SET ts = excluded.ts
+        "#,
+        identity.did
+    )
+    .execute(&mut **tx)
+    .await?;
+    tracing::info!(
+        action = "identity",
+        seq = identity.seq,
+        did = identity.did,
+        handle = identity.handle,
     );
-
-    tx.commit().await?;
     Ok(())
 }
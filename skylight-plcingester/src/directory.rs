@@ -1,21 +1,28 @@
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Entry {
     pub did: String,
-    pub operation: Operation,
+    /// The operation exactly as exported, since that is what its CID and signature are over.
+    pub operation: serde_json::Value,
     pub cid: String,
     pub nullified: bool,
     pub created_at: String,
 }
 
-#[derive(serde::Deserialize, Debug)]
+impl Entry {
+    pub fn parse_operation(&self) -> Result<Operation, serde_json::Error> {
+        serde::Deserialize::deserialize(&self.operation)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Service {
     pub r#type: String,
     pub endpoint: String,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct PlcOperation {
     pub rotation_keys: Vec<String>,
@@ -26,14 +33,14 @@ pub struct PlcOperation {
     pub sig: String,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct PlcTombstone {
     pub prev: String,
     pub sig: String,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Create {
     pub signing_key: String,
@@ -44,7 +51,7 @@ pub struct Create {
     pub sig: String,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 #[serde(tag = "type")]
 pub enum Operation {
     #[serde(rename = "plc_operation")]
@@ -56,3 +63,144 @@ pub enum Operation {
     #[serde(rename = "create")]
     Create(Create),
 }
+
+impl Operation {
+    pub fn r#type(&self) -> &'static str {
+        match self {
+            Operation::PlcOperation(_) => "plc_operation",
+            Operation::PlcTombstone(_) => "plc_tombstone",
+            Operation::Create(_) => "create",
+        }
+    }
+
+    /// The CID of the operation this one follows on from, or None for a genesis operation.
+    pub fn prev(&self) -> Option<&str> {
+        match self {
+            Operation::PlcOperation(operation) => operation.prev.as_deref(),
+            Operation::PlcTombstone(tombstone) => Some(&tombstone.prev),
+            Operation::Create(_) => None,
+        }
+    }
+}
+
+/// The state of a DID as of its latest operation, as served by plc.directory's `/{did}/data`.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DidData {
+    pub rotation_keys: Vec<String>,
+    pub verification_methods: std::collections::HashMap<String, String>,
+    pub also_known_as: Vec<String>,
+    pub services: std::collections::HashMap<String, Service>,
+}
+
+impl DidData {
+    /// The key that signs the DID's repo commits.
+    pub fn signing_key(&self) -> Option<&str> {
+        self.verification_methods.get("atproto").map(|v| v.as_str())
+    }
+
+    /// The endpoint of the PDS hosting the DID's repo.
+    pub fn pds_endpoint(&self) -> Option<&str> {
+        self.services
+            .get("atproto_pds")
+            .map(|service| service.endpoint.as_str())
+    }
+}
+
+impl From<PlcOperation> for DidData {
+    fn from(operation: PlcOperation) -> Self {
+        Self {
+            rotation_keys: operation.rotation_keys,
+            verification_methods: operation.verification_methods,
+            also_known_as: operation.also_known_as,
+            services: operation.services,
+        }
+    }
+}
+
+impl From<Create> for DidData {
+    /// Legacy genesis operations are read as their equivalent plc_operation.
+    fn from(create: Create) -> Self {
+        Self {
+            rotation_keys: vec![create.recovery_key, create.signing_key.clone()],
+            verification_methods: [("atproto".to_string(), create.signing_key)].into(),
+            also_known_as: vec![format!("at://{}", create.handle)],
+            services: [(
+                "atproto_pds".to_string(),
+                Service {
+                    r#type: "AtprotoPersonalDataServer".to_string(),
+                    endpoint: create.service,
+                },
+            )]
+            .into(),
+        }
+    }
+}
+
+/// A PLC directory, queried at no more than the rate plc.directory allows across everything that
+/// shares the client.
+pub struct Client {
+    client: reqwest::Client,
+    host: String,
+    rl: governor::DefaultDirectRateLimiter,
+    /// Callers wait for the rate limiter in turn, so that the export poller, which asks again as
+    /// soon as it has a page, doesn't starve the refresher.
+    turn: tokio::sync::Mutex<()>,
+}
+
+impl Client {
+    pub fn new(client: reqwest::Client, host: String) -> Self {
+        Self {
+            client,
+            host,
+            rl: governor::RateLimiter::direct(governor::Quota::per_second(
+                std::num::NonZeroU32::new(500 / (5 * 60)).unwrap(),
+            )),
+            turn: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Fetches `path`, or `None` if the directory doesn't know of it.
+    async fn get(&self, path: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        {
+            let _turn = self.turn.lock().await;
+            self.rl.until_ready().await;
+        }
+        let resp = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            self.client.get(format!("{}{}", self.host, path)).send(),
+        )
+        .await??;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            resp.error_for_status()?.bytes(),
+        )
+        .await??;
+        Ok(Some(body.to_vec()))
+    }
+
+    /// The next page of the export of every DID's operations, after the entry created at `after`.
+    pub async fn export(&self, after: Option<&str>) -> Result<Vec<Entry>, anyhow::Error> {
+        let mut path = "/export?limit=1000".to_string();
+        if let Some(after) = after {
+            path.push_str(&format!("&after={}", after));
+        }
+        let body = self
+            .get(&path)
+            .await?
+            .ok_or_else(|| anyhow::format_err!("{} has no /export", self.host))?;
+        Ok(crate::oplog::parse_entries(&body)?)
+    }
+
+    /// Every operation of a DID, including nullified ones, in the order they were created. `None`
+    /// if the DID doesn't exist.
+    pub async fn audit_log(&self, did: &str) -> Result<Option<Vec<Entry>>, anyhow::Error> {
+        match self.get(&format!("/{}/log/audit", did)).await? {
+            Some(body) => Ok(Some(serde_json::from_slice(&body)?)),
+            None => Ok(None),
+        }
+    }
+}
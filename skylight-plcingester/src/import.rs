@@ -0,0 +1,306 @@
+//! Bootstrapping from a JSONL export on disk, e.g. a snapshot, instead of paging through /export.
+
+use std::io::{BufRead, Read};
+
+/// The CRC-32 of gzip trailers.
+static CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Decompresses a gzip file, including one made of several concatenated members as written by pigz
+/// or `cat a.gz b.gz`, checking each member against the CRC-32 and size in its trailer.
+struct GzDecoder<R> {
+    inner: R,
+    state: Box<miniz_oxide::inflate::stream::InflateState>,
+    in_member: bool,
+    /// Of what has been inflated from the current member so far.
+    digest: crc::Digest<'static, u32>,
+    size: u32,
+}
+
+impl<R: std::io::BufRead> GzDecoder<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            state: miniz_oxide::inflate::stream::InflateState::new_boxed(
+                miniz_oxide::DataFormat::Raw,
+            ),
+            in_member: false,
+            digest: CRC.digest(),
+            size: 0,
+        }
+    }
+
+    /// Skips a member's header, leaving the reader at its deflate stream.
+    fn read_header(&mut self) -> std::io::Result<()> {
+        const FHCRC: u8 = 1 << 1;
+        const FEXTRA: u8 = 1 << 2;
+        const FNAME: u8 = 1 << 3;
+        const FCOMMENT: u8 = 1 << 4;
+
+        let mut header = [0; 10];
+        self.inner.read_exact(&mut header)?;
+        if header[..3] != [0x1f, 0x8b, 8] {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a gzip member",
+            ));
+        }
+        let flags = header[3];
+        if flags & FEXTRA != 0 {
+            let mut len = [0; 2];
+            self.inner.read_exact(&mut len)?;
+            std::io::copy(
+                &mut (&mut self.inner).take(u16::from_le_bytes(len) as u64),
+                &mut std::io::sink(),
+            )?;
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                self.inner.read_until(0, &mut vec![])?;
+            }
+        }
+        if flags & FHCRC != 0 {
+            self.inner.read_exact(&mut [0; 2])?;
+        }
+        Ok(())
+    }
+
+    /// Checks a member's trailer against what was inflated from it.
+    fn read_trailer(&mut self) -> std::io::Result<()> {
+        let mut trailer = [0; 8];
+        self.inner.read_exact(&mut trailer)?;
+        let crc = std::mem::replace(&mut self.digest, CRC.digest()).finalize();
+        // ISIZE is the size modulo 2^32.
+        let size = std::mem::take(&mut self.size);
+        if trailer[..4] != crc.to_le_bytes() || trailer[4..] != size.to_le_bytes() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "gzip member doesn't match its CRC-32 or size",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: std::io::BufRead> std::io::Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if !self.in_member {
+                if self.inner.fill_buf()?.is_empty() {
+                    return Ok(0);
+                }
+                self.read_header()?;
+                self.state.reset(miniz_oxide::DataFormat::Raw);
+                self.in_member = true;
+            }
+
+            let input = self.inner.fill_buf()?;
+            let eof = input.is_empty();
+            let result = miniz_oxide::inflate::stream::inflate(
+                &mut self.state,
+                input,
+                buf,
+                miniz_oxide::MZFlush::None,
+            );
+            self.inner.consume(result.bytes_consumed);
+            self.digest.update(&buf[..result.bytes_written]);
+            self.size = self.size.wrapping_add(result.bytes_written as u32);
+            match result.status {
+                Ok(miniz_oxide::MZStatus::StreamEnd) => {
+                    self.read_trailer()?;
+                    self.in_member = false;
+                }
+                Ok(_) | Err(miniz_oxide::MZError::Buf) if !eof || result.bytes_written > 0 => {}
+                Ok(_) | Err(miniz_oxide::MZError::Buf) => {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                Err(e) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("inflating failed: {e:?}"),
+                    ));
+                }
+            }
+            if result.bytes_written > 0 {
+                return Ok(result.bytes_written);
+            }
+        }
+    }
+}
+
+/// Opens an export, decompressing it if it starts with the gzip magic.
+fn open(path: &std::path::Path) -> std::io::Result<Box<dyn std::io::BufRead + Send>> {
+    let mut r = std::io::BufReader::new(std::fs::File::open(path)?);
+    Ok(if r.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(std::io::BufReader::new(GzDecoder::new(r)))
+    } else {
+        Box::new(r)
+    })
+}
+
+/// Reads an export in batches, leaving out entries at or before the cursor, e.g. those an
+/// interrupted import already applied.
+fn read_batches(
+    path: &std::path::Path,
+    cursor: Option<String>,
+    batch_size: usize,
+    batches_tx: tokio::sync::mpsc::Sender<(Vec<crate::directory::Entry>, usize)>,
+) -> Result<(), anyhow::Error> {
+    let mut r = open(path)?;
+    let mut line = String::new();
+    loop {
+        let mut entries = Vec::with_capacity(batch_size);
+        let mut passed = 0;
+        while entries.len() < batch_size {
+            line.clear();
+            if r.read_line(&mut line)? == 0 {
+                break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: crate::directory::Entry = serde_json::from_str(&line)?;
+            if cursor.as_ref().is_some_and(|c| entry.created_at <= *c) {
+                passed += 1;
+                continue;
+            }
+            entries.push(entry);
+        }
+        if entries.is_empty() && passed == 0 {
+            return Ok(());
+        }
+        if batches_tx.blocking_send((entries, passed)).is_err() {
+            // The writer has stopped, and will report why.
+            return Ok(());
+        }
+    }
+}
+
+/// Applies an export from disk, advancing the cursor as it goes so that polling /export resumes
+/// where it ends.
+pub async fn import_main(
+    conn: &mut sqlx::postgres::PgConnection,
+    path: std::path::PathBuf,
+    verify: bool,
+    batch_size: usize,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+) -> Result<(), anyhow::Error> {
+    let cursor = skylight_common::cursors::plc(&mut *conn).await?;
+    tracing::info!(action = "import", path = ?path, cursor = cursor);
+
+    let (batches_tx, mut batches_rx) = tokio::sync::mpsc::channel(2);
+    let reader =
+        tokio::task::spawn_blocking(move || read_batches(&path, cursor, batch_size, batches_tx));
+
+    let mut total = crate::oplog::Counts::default();
+    while let Some((entries, passed)) = batches_rx.recv().await {
+        if *shutdown_rx.borrow() {
+            tracing::info!(action = "import", "interrupted");
+            return Ok(());
+        }
+
+        let counts = crate::oplog::append_batch(conn, &entries, verify).await?;
+        total.appended += counts.appended;
+        total.unverified += counts.unverified;
+        total.quarantined += counts.quarantined;
+        total.skipped += counts.skipped + passed;
+        tracing::info!(
+            action = "import",
+            appended = total.appended,
+            unverified = total.unverified,
+            quarantined = total.quarantined,
+            skipped = total.skipped,
+            cursor = entries.last().map(|entry| &entry.created_at)
+        );
+    }
+    reader.await??;
+
+    tracing::info!(
+        action = "import",
+        appended = total.appended,
+        unverified = total.unverified,
+        quarantined = total.quarantined,
+        skipped = total.skipped,
+        "done"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    /// A gzip member of `data` with the given header flags and fields after the fixed header.
+    fn member(data: &[u8], flags: u8, fields: &[u8]) -> Vec<u8> {
+        let mut member = vec![0x1f, 0x8b, 8, flags, 0, 0, 0, 0, 0, 0xff];
+        member.extend(fields);
+        member.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+        member.extend(super::CRC.checksum(data).to_le_bytes());
+        member.extend((data.len() as u32).to_le_bytes());
+        member
+    }
+
+    fn decode(gz: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = vec![];
+        super::GzDecoder::new(gz).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn members() {
+        let mut gz = member(b"a\n", 0, &[]);
+        gz.extend(member(b"", 0, &[]));
+        gz.extend(member(&b"b\n".repeat(100_000), 0, &[]));
+        let mut expected = b"a\n".to_vec();
+        expected.extend(b"b\n".repeat(100_000));
+        assert_eq!(decode(&gz).unwrap(), expected);
+    }
+
+    #[test]
+    fn header_fields() {
+        const FHCRC: u8 = 1 << 1;
+        const FEXTRA: u8 = 1 << 2;
+        const FNAME: u8 = 1 << 3;
+        const FCOMMENT: u8 = 1 << 4;
+
+        let gz = member(
+            b"a\n",
+            FHCRC | FEXTRA | FNAME | FCOMMENT,
+            b"\x04\x00ab\x02\x00export.jsonl\x00a comment\x00\x12\x34",
+        );
+        assert_eq!(decode(&gz).unwrap(), b"a\n");
+    }
+
+    #[test]
+    fn truncated() {
+        let gz = member(&b"b\n".repeat(1000), 0, &[]);
+        for len in [5, 12, gz.len() / 2, gz.len() - 4] {
+            assert_eq!(
+                decode(&gz[..len]).unwrap_err().kind(),
+                std::io::ErrorKind::UnexpectedEof
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let gz = member(b"a\n", 0, &[]);
+        let len = gz.len();
+
+        let mut bad_crc = gz.clone();
+        bad_crc[len - 8] ^= 1;
+        assert_eq!(
+            decode(&bad_crc).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        let mut bad_size = gz.clone();
+        bad_size[len - 4] ^= 1;
+        assert_eq!(
+            decode(&bad_size).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+}
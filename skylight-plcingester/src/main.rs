@@ -1,7 +1,10 @@
 mod directory;
+mod import;
+mod oplog;
+mod refresh;
+mod verify;
 
 use clap::Parser;
-use sqlx::Connection;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -9,95 +12,136 @@ struct Args {
     #[arg(long, default_value = "postgres:///skylight")]
     dsn: String,
 
+    /// Apply pending schema migrations before starting. Without this, the binary refuses to run
+    /// against a database whose schema isn't the version it was built against.
+    #[arg(long, default_value_t = false)]
+    migrate: bool,
+
     #[arg(long, default_value = "https://plc.directory")]
     plcdirectory_host: String,
+
+    /// Apply entries without checking their CIDs, chains and signatures. Even with checking,
+    /// entries of DIDs whose log in plc.operations doesn't start at their genesis operation are
+    /// applied unchecked until the refresher has filled in the log.
+    #[arg(long, default_value_t = false)]
+    skip_verification: bool,
+
+    /// Apply a JSONL export from this file, optionally gzip-compressed, instead of polling
+    /// /export, then exit. Entries at or before the cursor are skipped, so an interrupted import
+    /// can be rerun and polling resumes where the file ends.
+    #[arg(long)]
+    import: Option<std::path::PathBuf>,
+
+    /// How many entries of an imported file to apply per transaction.
+    #[arg(long, default_value_t = 1000)]
+    import_batch_size: usize,
+}
+
+/// Records the current state of a DID.
+pub async fn set_did(
+    conn: &mut sqlx::postgres::PgConnection,
+    did: &str,
+    data: &directory::DidData,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO plc.dids (
+            did,
+            also_known_as,
+            rotation_keys,
+            verification_methods,
+            services,
+            signing_key,
+            pds_endpoint
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (did) DO
+        UPDATE SET
+            also_known_as = excluded.also_known_as,
+            rotation_keys = excluded.rotation_keys,
+            verification_methods = excluded.verification_methods,
+            services = excluded.services,
+            signing_key = excluded.signing_key,
+            pds_endpoint = excluded.pds_endpoint
+        "#,
+        did,
+        &data
+            .also_known_as
+            .iter()
+            .filter(|v| v.len() <= 512)
+            .cloned()
+            .collect::<Vec<_>>(),
+        &data.rotation_keys,
+        serde_json::to_value(&data.verification_methods)?,
+        serde_json::to_value(&data.services)?,
+        data.signing_key(),
+        data.pds_endpoint()
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(tracing::Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    skylight_common::logging::init();
 
     let args = Args::parse();
 
-    let mut conn = sqlx::postgres::PgConnection::connect(&args.dsn).await?;
+    let (conn_options, mut conn) = skylight_common::db::connect(&args.dsn, args.migrate).await?;
 
-    let mut cursor = sqlx::query!("SELECT cursor FROM plc.cursor")
-        .fetch_optional(&mut conn)
-        .await?
-        .map(|v| v.cursor);
+    let mut cursor = skylight_common::cursors::plc(&mut conn).await?;
     tracing::info!(cursor = cursor);
 
-    let rl = governor::RateLimiter::direct(governor::Quota::per_second(
-        std::num::NonZeroU32::new(500 / (5 * 60)).unwrap(),
-    ));
+    let mut shutdown_rx = skylight_common::shutdown::watch();
+
+    if let Some(path) = args.import {
+        return import::import_main(
+            &mut conn,
+            path,
+            !args.skip_verification,
+            args.import_batch_size,
+            shutdown_rx,
+        )
+        .await;
+    }
 
     let client = reqwest::Client::new();
-    loop {
-        let mut url = format!("{}/export?limit=1000", args.plcdirectory_host);
-        if let Some(cursor) = cursor.as_ref() {
-            url.push_str(&format!("&after={}", cursor));
-        }
+    let directory = std::sync::Arc::new(directory::Client::new(
+        client.clone(),
+        args.plcdirectory_host.clone(),
+    ));
 
-        rl.until_ready().await;
-        for line in tokio::time::timeout(
-            std::time::Duration::from_secs(30),
-            tokio::time::timeout(std::time::Duration::from_secs(10), client.get(url).send())
-                .await??
-                .error_for_status()?
-                .bytes(),
-        )
-        .await??
-        .split(|c| *c == '\n' as u8)
-        {
-            let entry: directory::Entry = serde_json::from_slice(&line)?;
-            tracing::info!(entry = ?entry);
-
-            let mut tx = conn.begin().await?;
-            {
-                let operation = match entry.operation {
-                    directory::Operation::Create(create) => {
-                        directory::Operation::PlcOperation(directory::PlcOperation {
-                            rotation_keys: vec![],
-                            verification_methods: std::collections::HashMap::new(),
-                            also_known_as: vec![format!("at://{}", create.handle)],
-                            services: std::collections::HashMap::new(),
-                            prev: None,
-                            sig: create.sig,
-                        })
-                    }
-                    operation => operation,
-                };
-                match operation {
-                    directory::Operation::PlcOperation(operation) => {
-                        sqlx::query!(
-                            "INSERT INTO plc.dids (did, also_known_as) VALUES ($1, $2) ON CONFLICT (did) DO UPDATE SET also_known_as = excluded.also_known_as",
-                            entry.did,
-                            &operation.also_known_as.into_iter().filter(|v| v.len() <= 512).collect::<Vec<_>>()
-                        )
-                        .execute(&mut *tx)
-                        .await?;
-                    }
-                    directory::Operation::PlcTombstone(_) => {
-                        sqlx::query!("DELETE FROM plc.dids WHERE did = $1", entry.did)
-                            .execute(&mut *tx)
-                            .await?;
-                    }
-                    directory::Operation::Create(_) => {
-                        unreachable!("should have been transformed into a plc_operation");
-                    }
-                }
+    tokio::spawn(refresh::refresh_main(
+        std::sync::Arc::clone(&directory),
+        client,
+        conn_options,
+        !args.skip_verification,
+        shutdown_rx.clone(),
+    ));
+
+    while !*shutdown_rx.borrow() {
+        let entries = tokio::select! {
+            r = directory.export(cursor.as_deref()) => r?,
+            _ = shutdown_rx.wait_for(|v| *v) => {
+                break;
             }
-            sqlx::query!(
-                "INSERT INTO plc.cursor (cursor) VALUES ($1) ON CONFLICT ((0)) DO UPDATE SET cursor = excluded.cursor",
-                entry.created_at
-            )
-            .execute(&mut *tx)
-            .await?;
-            cursor = Some(entry.created_at);
-            tx.commit().await?;
+        };
+
+        let counts = oplog::append_batch(&mut conn, &entries, !args.skip_verification).await?;
+        if let Some(last) = entries.last() {
+            cursor = Some(last.created_at.clone());
+            tracing::info!(
+                action = "page",
+                entries = entries.len(),
+                appended = counts.appended,
+                unverified = counts.unverified,
+                quarantined = counts.quarantined,
+                skipped = counts.skipped,
+                cursor = cursor
+            );
         }
     }
+
+    Ok(())
 }
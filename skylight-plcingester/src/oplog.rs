@@ -0,0 +1,443 @@
+//! The log of operations of each DID, and the current state derived from it.
+
+/// Sets aside an entry that failed verification.
+async fn quarantine(
+    conn: &mut sqlx::postgres::PgConnection,
+    entry: &crate::directory::Entry,
+    created_at: time::OffsetDateTime,
+    failure: &crate::verify::Failure,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"--sql
+        INSERT INTO plc.quarantine (did, cid, operation, nullified, created_at, reason)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (did, cid) DO
+        UPDATE SET reason = excluded.reason, ts = NOW()
+        "#,
+        entry.did,
+        entry.cid,
+        entry.operation,
+        entry.nullified,
+        created_at,
+        failure.to_string()
+    )
+    .execute(conn)
+    .await?;
+    tracing::warn!(
+        action = "quarantine",
+        did = entry.did,
+        cid = entry.cid,
+        reason = failure.reason(),
+        error = failure.to_string()
+    );
+    Ok(())
+}
+
+/// What became of an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Appended,
+    /// Appended without verification, because the DID's log doesn't go back to its genesis
+    /// operation.
+    Unverified,
+    Quarantined,
+    /// Already in the log.
+    Skipped,
+}
+
+/// Whether the log of `did` starts at its genesis operation, so that later operations can be
+/// verified against it. It doesn't for DIDs created before the ingester's cursor, e.g. when the
+/// cursor was carried over from before plc.operations existed.
+async fn has_genesis(
+    conn: &mut sqlx::postgres::PgConnection,
+    did: &str,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"--sql
+        SELECT EXISTS (
+            SELECT *
+            FROM plc.operations
+            WHERE did = $1 AND prev IS NULL
+        ) AS "exists!"
+        "#,
+        did
+    )
+    .fetch_one(conn)
+    .await?
+    .exists)
+}
+
+/// Where an entry came from, which decides what its `nullified` flag is taken to mean.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The export, in order of creation. An entry that forks the log is appended before the
+    /// directory marks what it nullifies, so that is inferred from its prev.
+    Export,
+    /// A DID's audit log, which is complete and whose `nullified` flags are up to date. They are
+    /// taken as they are, including for entries already in the log.
+    AuditLog,
+}
+
+/// Updates plc.dids to the state as of the latest operation of `did` that isn't nullified.
+async fn set_head(conn: &mut sqlx::postgres::PgConnection, did: &str) -> Result<(), anyhow::Error> {
+    let head = sqlx::query!(
+        r#"--sql
+        SELECT operation
+        FROM plc.operations
+        WHERE did = $1 AND NOT nullified
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+        did
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .map(|r| serde_json::from_value::<crate::directory::Operation>(r.operation))
+    .transpose()?;
+
+    match head {
+        Some(crate::directory::Operation::PlcOperation(operation)) => {
+            crate::set_did(conn, did, &operation.into()).await?;
+        }
+        Some(crate::directory::Operation::Create(create)) => {
+            crate::set_did(conn, did, &create.into()).await?;
+        }
+        Some(crate::directory::Operation::PlcTombstone(_)) | None => {
+            sqlx::query!("DELETE FROM plc.dids WHERE did = $1", did)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends an entry to its DID's log and updates plc.dids to the state as of the latest operation
+/// that isn't nullified. Entries of the export that fork the log nullify the operations they fork
+/// away from. Entries already in the log are skipped, though an audit log's may still change
+/// whether they are nullified. With `verify`, entries that fail verification are quarantined
+/// instead.
+///
+/// Entries of DIDs whose log doesn't start at their genesis operation can't be verified, so they
+/// are appended as they are and the DID is queued for the refresher, which fills in the log from
+/// the directory's audit log. Verification applies once it has.
+pub async fn append(
+    conn: &mut sqlx::postgres::PgConnection,
+    entry: &crate::directory::Entry,
+    verify: bool,
+    source: Source,
+) -> Result<Outcome, anyhow::Error> {
+    let created_at = time::OffsetDateTime::parse(
+        &entry.created_at,
+        &time::format_description::well_known::Rfc3339,
+    )?;
+
+    let operation = match entry.parse_operation() {
+        Ok(operation) => operation,
+        Err(e) => {
+            let failure = crate::verify::Failure::Malformed(e.to_string());
+            quarantine(conn, entry, created_at, &failure).await?;
+            return Ok(Outcome::Quarantined);
+        }
+    };
+
+    let exists = sqlx::query!(
+        r#"--sql
+        SELECT EXISTS (
+            SELECT *
+            FROM plc.operations
+            WHERE did = $1 AND cid = $2
+        ) AS "exists!"
+        "#,
+        entry.did,
+        entry.cid
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .exists;
+    if exists {
+        if source == Source::AuditLog {
+            let updated = sqlx::query!(
+                r#"--sql
+                UPDATE plc.operations
+                SET nullified = $3
+                WHERE did = $1 AND cid = $2 AND nullified != $3
+                "#,
+                entry.did,
+                entry.cid,
+                entry.nullified
+            )
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+            if updated > 0 {
+                tracing::info!(
+                    action = "set nullified",
+                    did = entry.did,
+                    cid = entry.cid,
+                    nullified = entry.nullified
+                );
+                set_head(conn, &entry.did).await?;
+            }
+        }
+        return Ok(Outcome::Skipped);
+    }
+
+    let mut outcome = Outcome::Appended;
+    if verify {
+        if operation.prev().is_some() && !has_genesis(conn, &entry.did).await? {
+            sqlx::query!(
+                r#"--sql
+                INSERT INTO plc.refresh_queue (did)
+                VALUES ($1)
+                ON CONFLICT DO
+                NOTHING
+                "#,
+                entry.did
+            )
+            .execute(&mut *conn)
+            .await?;
+            outcome = Outcome::Unverified;
+        } else if let Err(failure) =
+            crate::verify::check(conn, entry, &operation, created_at).await?
+        {
+            quarantine(conn, entry, created_at, &failure).await?;
+            return Ok(Outcome::Quarantined);
+        }
+    }
+
+    let inserted = sqlx::query!(
+        r#"--sql
+        INSERT INTO plc.operations (
+            did,
+            cid,
+            prev,
+            type,
+            operation,
+            nullified,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (did, cid) DO NOTHING
+        "#,
+        entry.did,
+        entry.cid,
+        operation.prev(),
+        operation.r#type(),
+        entry.operation,
+        entry.nullified,
+        created_at
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    if inserted == 0 {
+        return Ok(Outcome::Skipped);
+    }
+
+    if source == Source::Export && !entry.nullified {
+        if let Some(prev) = operation.prev() {
+            // Usually nothing else follows prev and this nullifies nothing. If something does,
+            // e.g. because a rotation key recovered the DID, it and everything after it are
+            // overridden.
+            let nullified = sqlx::query!(
+                r#"--sql
+                WITH RECURSIVE forked AS (
+                    SELECT cid
+                    FROM plc.operations
+                    WHERE
+                        did = $1 AND
+                        prev = $3 AND
+                        cid != $2 AND
+                        NOT nullified AND
+                        created_at < $4
+                    UNION
+                    SELECT o.cid
+                    FROM plc.operations AS o
+                    INNER JOIN forked AS f ON o.prev = f.cid
+                    WHERE o.did = $1
+                )
+                UPDATE plc.operations
+                SET nullified = TRUE
+                WHERE
+                    did = $1 AND
+                    NOT nullified AND
+                    cid IN (SELECT cid FROM forked)
+                "#,
+                entry.did,
+                entry.cid,
+                prev,
+                created_at
+            )
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+            if nullified > 0 {
+                tracing::info!(
+                    action = "nullify",
+                    did = entry.did,
+                    cid = entry.cid,
+                    prev = prev,
+                    nullified = nullified
+                );
+            }
+        }
+    }
+
+    set_head(conn, &entry.did).await?;
+    Ok(outcome)
+}
+
+/// How many entries of a batch had each outcome.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Counts {
+    pub appended: usize,
+    pub unverified: usize,
+    pub quarantined: usize,
+    pub skipped: usize,
+}
+
+/// Appends a batch of export entries in a single transaction, advancing the cursor to the last of
+/// them.
+pub async fn append_batch(
+    conn: &mut sqlx::postgres::PgConnection,
+    entries: &[crate::directory::Entry],
+    verify: bool,
+) -> Result<Counts, anyhow::Error> {
+    let Some(last) = entries.last() else {
+        return Ok(Counts::default());
+    };
+
+    let mut counts = Counts::default();
+    let mut tx = sqlx::Connection::begin(conn).await?;
+    for entry in entries {
+        tracing::debug!(entry = ?entry);
+        match append(&mut tx, entry, verify, Source::Export).await? {
+            Outcome::Appended => counts.appended += 1,
+            Outcome::Unverified => counts.unverified += 1,
+            Outcome::Quarantined => counts.quarantined += 1,
+            Outcome::Skipped => counts.skipped += 1,
+        }
+    }
+    skylight_common::cursors::set_plc(&mut *tx, &last.created_at).await?;
+    tx.commit().await?;
+    Ok(counts)
+}
+
+/// Parses the entries of an export page or file, skipping blank lines.
+pub fn parse_entries(body: &[u8]) -> Result<Vec<crate::directory::Entry>, serde_json::Error> {
+    body.split(|c| *c == b'\n')
+        .filter(|line| !line.trim_ascii().is_empty())
+        .map(serde_json::from_slice)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outcome, Source};
+
+    // A log signed with the same keys as verify's tests: a genesis operation and an update by the
+    // secp256k1 rotation key, then another update by it that the P-256 one forks away from, as
+    // the audit log has it.
+    fn log() -> Vec<crate::directory::Entry> {
+        super::parse_entries(include_bytes!("../testdata/fork.jsonl")).unwrap()
+    }
+
+    async fn append(
+        conn: &mut sqlx::postgres::PgConnection,
+        entries: &[crate::directory::Entry],
+        source: Source,
+    ) -> Vec<Outcome> {
+        let mut outcomes = vec![];
+        for entry in entries {
+            outcomes.push(super::append(conn, entry, true, source).await.unwrap());
+        }
+        outcomes
+    }
+
+    async fn also_known_as(conn: &mut sqlx::postgres::PgConnection, did: &str) -> Vec<String> {
+        sqlx::query!("SELECT also_known_as FROM plc.dids WHERE did = $1", did)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+            .also_known_as
+    }
+
+    async fn nullified(conn: &mut sqlx::postgres::PgConnection, did: &str) -> Vec<String> {
+        sqlx::query!(
+            "SELECT cid FROM plc.operations WHERE did = $1 AND nullified ORDER BY created_at",
+            did
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.cid)
+        .collect()
+    }
+
+    async fn quarantined(conn: &mut sqlx::postgres::PgConnection) -> i64 {
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM plc.quarantine"#)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+            .count
+    }
+
+    #[sqlx::test(migrator = "skylight_common::schema::MIGRATOR")]
+    async fn fork(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let mut log = log();
+        let did = log[0].did.clone();
+        // The export has each entry as it was created.
+        for entry in &mut log {
+            entry.nullified = false;
+        }
+
+        assert_eq!(
+            append(&mut conn, &log, Source::Export).await,
+            [Outcome::Appended; 4]
+        );
+        assert_eq!(nullified(&mut conn, &did).await, [log[2].cid.clone()]);
+        assert_eq!(also_known_as(&mut conn, &did).await, ["at://alice4.test"]);
+        assert_eq!(quarantined(&mut conn).await, 0);
+    }
+
+    #[sqlx::test(migrator = "skylight_common::schema::MIGRATOR")]
+    async fn backfill(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let log = log();
+        let did = log[0].did.clone();
+
+        // The export's cursor starts after the first two operations.
+        let mut tail = log[2].clone();
+        tail.nullified = false;
+        assert_eq!(
+            append(&mut conn, &[tail.clone()], Source::Export).await,
+            [Outcome::Unverified]
+        );
+        assert_eq!(also_known_as(&mut conn, &did).await, ["at://alice3.test"]);
+
+        // The refresher fills in the log from before the fork.
+        let before_fork = [log[0].clone(), log[1].clone(), tail];
+        assert_eq!(
+            append(&mut conn, &before_fork, Source::AuditLog).await,
+            [Outcome::Appended, Outcome::Appended, Outcome::Skipped]
+        );
+        assert!(nullified(&mut conn, &did).await.is_empty());
+        assert_eq!(also_known_as(&mut conn, &did).await, ["at://alice3.test"]);
+
+        // And again after it.
+        assert_eq!(
+            append(&mut conn, &log, Source::AuditLog).await,
+            [
+                Outcome::Skipped,
+                Outcome::Skipped,
+                Outcome::Skipped,
+                Outcome::Appended
+            ]
+        );
+        assert_eq!(nullified(&mut conn, &did).await, [log[2].cid.clone()]);
+        assert_eq!(also_known_as(&mut conn, &did).await, ["at://alice4.test"]);
+        assert_eq!(quarantined(&mut conn).await, 0);
+    }
+}
@@ -0,0 +1,284 @@
+use sqlx::Connection;
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMethod {
+    id: String,
+    public_key_multibase: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Service {
+    id: String,
+    r#type: String,
+    service_endpoint: String,
+}
+
+/// A W3C DID document, as served for did:web DIDs.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DidDocument {
+    #[serde(default)]
+    also_known_as: Vec<String>,
+    #[serde(default)]
+    verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
+    service: Vec<Service>,
+}
+
+/// The part of a DID document id after the `#`, e.g. `atproto` for `did:web:example.com#atproto`.
+fn fragment(id: &str) -> &str {
+    id.rsplit_once('#')
+        .map(|(_, fragment)| fragment)
+        .unwrap_or(id)
+}
+
+impl From<DidDocument> for crate::directory::DidData {
+    /// did:web DIDs have no rotation keys, since they're controlled by whoever controls the
+    /// domain.
+    fn from(doc: DidDocument) -> Self {
+        Self {
+            rotation_keys: vec![],
+            verification_methods: doc
+                .verification_method
+                .into_iter()
+                .filter_map(|method| {
+                    Some((
+                        fragment(&method.id).to_string(),
+                        format!("did:key:{}", method.public_key_multibase?),
+                    ))
+                })
+                .collect(),
+            also_known_as: doc.also_known_as,
+            services: doc
+                .service
+                .into_iter()
+                .map(|service| {
+                    (
+                        fragment(&service.id).to_string(),
+                        crate::directory::Service {
+                            r#type: service.r#type,
+                            endpoint: service.service_endpoint,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Where a did:web DID's document is served: the first part of the DID is the host, with any
+/// port's colon percent-encoded, and the rest are path segments, e.g.
+/// `did:web:example.com:u:alice` is at `https://example.com/u/alice/did.json`.
+fn did_web_url(id: &str) -> String {
+    let mut parts = id.split(':');
+    let host = parts.next().unwrap_or_default().replace("%3A", ":");
+    let path = parts.collect::<Vec<_>>();
+    if path.is_empty() {
+        format!("https://{}/.well-known/did.json", host)
+    } else {
+        format!("https://{}/{}/did.json", host, path.join("/"))
+    }
+}
+
+/// Fetches the current state of a did:web DID, or `None` if it no longer exists.
+async fn resolve_web(
+    client: &reqwest::Client,
+    id: &str,
+) -> Result<Option<crate::directory::DidData>, anyhow::Error> {
+    let resp = tokio::time::timeout(
+        std::time::Duration::from_secs(10),
+        client.get(did_web_url(id)).send(),
+    )
+    .await??;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND || resp.status() == reqwest::StatusCode::GONE
+    {
+        return Ok(None);
+    }
+    let body = resp.error_for_status()?.bytes().await?;
+    Ok(Some(serde_json::from_slice::<DidDocument>(&body)?.into()))
+}
+
+/// What a queued DID resolved to.
+enum Resolved {
+    /// A did:plc DID's log, to be appended to plc.operations.
+    Plc(Vec<crate::directory::Entry>),
+    /// A did:web DID's document, or `None` if it no longer exists.
+    Web(Option<crate::directory::DidData>),
+    /// A did:plc DID the directory doesn't know of.
+    Unknown,
+}
+
+async fn resolve(
+    directory: &crate::directory::Client,
+    client: &reqwest::Client,
+    rl: &governor::DefaultDirectRateLimiter,
+    did: &str,
+) -> Result<Resolved, anyhow::Error> {
+    if did.starts_with("did:plc:") {
+        Ok(match directory.audit_log(did).await? {
+            Some(entries) => Resolved::Plc(entries),
+            None => Resolved::Unknown,
+        })
+    } else if let Some(id) = did.strip_prefix("did:web:") {
+        rl.until_ready().await;
+        Ok(Resolved::Web(resolve_web(client, id).await?))
+    } else {
+        Err(anyhow::format_err!("unsupported did method: {}", did))
+    }
+}
+
+/// Re-resolves the DIDs in plc.refresh_queue, which the firehose ingester fills with DIDs whose
+/// identity has changed. Each DID is resolved outside of any transaction and then dequeued along
+/// with writing what it resolved to, unless it was queued again in the meantime. DIDs that fail to
+/// resolve are retried after a few minutes.
+///
+/// did:plc DIDs are brought up to date by appending their audit log to plc.operations, which
+/// plc.dids is derived from, the same as entries of the export. Only did:web DIDs, which have no
+/// log, are written to plc.dids directly.
+async fn refresh(
+    directory: &crate::directory::Client,
+    client: &reqwest::Client,
+    conn: &mut sqlx::PgConnection,
+    rl: &governor::DefaultDirectRateLimiter,
+    verify: bool,
+    shutdown: &mut tokio::sync::watch::Receiver<bool>,
+) -> Result<(), anyhow::Error> {
+    loop {
+        if *shutdown.borrow() {
+            return Ok(());
+        }
+
+        let queued = sqlx::query!(
+            r#"--sql
+            SELECT did, ts
+            FROM plc.refresh_queue
+            WHERE ts IS NULL OR ts <= NOW()
+            ORDER BY ts
+            LIMIT 100
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        if queued.is_empty() {
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {}
+                _ = shutdown.wait_for(|v| *v) => {}
+            }
+            continue;
+        }
+
+        for queued in queued {
+            let did = queued.did;
+            let resolved = tokio::select! {
+                r = resolve(directory, client, rl, &did) => r,
+                _ = shutdown.wait_for(|v| *v) => {
+                    return Ok(());
+                }
+            };
+
+            let resolved = match resolved {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    tracing::error!(did = did, error = format!("{e:?}"));
+                    sqlx::query!(
+                        r#"--sql
+                        UPDATE plc.refresh_queue
+                        SET ts = NOW() + INTERVAL '5 minutes'
+                        WHERE did = $1 AND ts IS NOT DISTINCT FROM $2
+                        "#,
+                        did,
+                        queued.ts
+                    )
+                    .execute(&mut *conn)
+                    .await?;
+                    continue;
+                }
+            };
+
+            let mut tx = conn.begin().await?;
+            match resolved {
+                Resolved::Plc(entries) => {
+                    let mut counts = crate::oplog::Counts::default();
+                    for entry in entries.iter().filter(|entry| entry.did == did) {
+                        match crate::oplog::append(
+                            &mut tx,
+                            entry,
+                            verify,
+                            crate::oplog::Source::AuditLog,
+                        )
+                        .await?
+                        {
+                            crate::oplog::Outcome::Appended => counts.appended += 1,
+                            crate::oplog::Outcome::Unverified => counts.unverified += 1,
+                            crate::oplog::Outcome::Quarantined => counts.quarantined += 1,
+                            crate::oplog::Outcome::Skipped => counts.skipped += 1,
+                        }
+                    }
+                    tracing::info!(
+                        action = "refresh",
+                        did = did,
+                        appended = counts.appended,
+                        unverified = counts.unverified,
+                        quarantined = counts.quarantined,
+                        skipped = counts.skipped
+                    );
+                }
+                Resolved::Web(Some(data)) => {
+                    crate::set_did(&mut tx, &did, &data).await?;
+                    tracing::info!(action = "refresh", did = did);
+                }
+                Resolved::Web(None) => {
+                    sqlx::query!("DELETE FROM plc.dids WHERE did = $1", did)
+                        .execute(&mut *tx)
+                        .await?;
+                    tracing::info!(action = "refresh gone", did = did);
+                }
+                Resolved::Unknown => {
+                    tracing::warn!(action = "refresh unknown", did = did);
+                }
+            }
+            sqlx::query!(
+                r#"--sql
+                DELETE FROM plc.refresh_queue
+                WHERE did = $1 AND ts IS NOT DISTINCT FROM $2
+                "#,
+                did,
+                queued.ts
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+        }
+    }
+}
+
+/// Keeps [`refresh`] going until shutdown, reconnecting after errors.
+pub async fn refresh_main(
+    directory: std::sync::Arc<crate::directory::Client>,
+    client: reqwest::Client,
+    conn_options: sqlx::postgres::PgConnectOptions,
+    verify: bool,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) {
+    let rl = governor::RateLimiter::direct(governor::Quota::per_second(
+        std::num::NonZeroU32::new(10).unwrap(),
+    ));
+
+    while !*shutdown.borrow() {
+        let r = async {
+            let mut conn = sqlx::postgres::PgConnection::connect_with(&conn_options).await?;
+            refresh(&directory, &client, &mut conn, &rl, verify, &mut shutdown).await
+        }
+        .await;
+        if let Err(e) = r {
+            tracing::error!(error = format!("{e:?}"), "refresh failed");
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {}
+                _ = shutdown.wait_for(|v| *v) => {}
+            }
+        }
+    }
+}
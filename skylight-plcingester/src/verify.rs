@@ -0,0 +1,487 @@
+//! Checks that export entries are what the DID's rotation keys signed, so that a tampered mirror
+//! of plc.directory is noticed.
+
+#[derive(thiserror::Error, Debug)]
+pub enum Failure {
+    #[error("cid is {computed}, not {claimed}")]
+    CidMismatch { claimed: String, computed: String },
+
+    #[error("genesis operation is for {computed}")]
+    DidMismatch { computed: String },
+
+    #[error("prev {0} is not in the log")]
+    MissingPrev(String),
+
+    #[error("prev {0} is a tombstone")]
+    PrevTombstone(String),
+
+    #[error("signature is not by any rotation key of prev")]
+    BadSignature,
+
+    #[error("nullifies {nullified}, which is past the recovery window")]
+    RecoveryWindowClosed { nullified: String },
+
+    #[error(
+        "nullifies {nullified}, which is signed by a rotation key of the same or higher priority"
+    )]
+    NotHigherPriority { nullified: String },
+
+    #[error("malformed operation: {0}")]
+    Malformed(String),
+}
+
+impl Failure {
+    /// A short name for the kind of failure, for metrics and the quarantine table.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Failure::CidMismatch { .. } => "cid_mismatch",
+            Failure::DidMismatch { .. } => "did_mismatch",
+            Failure::MissingPrev(_) => "missing_prev",
+            Failure::PrevTombstone(_) => "prev_tombstone",
+            Failure::BadSignature => "bad_signature",
+            Failure::RecoveryWindowClosed { .. } => "recovery_window_closed",
+            Failure::NotHigherPriority { .. } => "not_higher_priority",
+            Failure::Malformed(_) => "malformed",
+        }
+    }
+}
+
+/// Converts JSON to DAG-CBOR's data model, with map keys in canonical order: shortest first, then
+/// bytewise.
+fn to_dagcbor(value: &serde_json::Value) -> ciborium::value::Value {
+    match value {
+        serde_json::Value::Null => ciborium::value::Value::Null,
+        serde_json::Value::Bool(v) => ciborium::value::Value::Bool(*v),
+        serde_json::Value::Number(v) => {
+            if let Some(v) = v.as_i64() {
+                ciborium::value::Value::Integer(v.into())
+            } else if let Some(v) = v.as_u64() {
+                ciborium::value::Value::Integer(v.into())
+            } else {
+                ciborium::value::Value::Float(v.as_f64().unwrap_or_default())
+            }
+        }
+        serde_json::Value::String(v) => ciborium::value::Value::Text(v.clone()),
+        serde_json::Value::Array(v) => {
+            ciborium::value::Value::Array(v.iter().map(to_dagcbor).collect())
+        }
+        serde_json::Value::Object(v) => {
+            let mut entries = v.iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+            ciborium::value::Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (ciborium::value::Value::Text(k.clone()), to_dagcbor(v)))
+                    .collect(),
+            )
+        }
+    }
+}
+
+fn encode(value: &serde_json::Value) -> Vec<u8> {
+    let mut buf = vec![];
+    ciborium::into_writer(&to_dagcbor(value), &mut buf).expect("writing to a Vec failed");
+    buf
+}
+
+fn sha256(buf: &[u8]) -> [u8; 32] {
+    use sha2::Digest;
+    sha2::Sha256::digest(buf).into()
+}
+
+/// The CID of a signed operation: CIDv1, DAG-CBOR, SHA-256.
+pub fn cid(operation: &serde_json::Value) -> Result<String, Failure> {
+    let mh = cid::multihash::Multihash::wrap(0x12, &sha256(&encode(operation)))
+        .map_err(|e| Failure::Malformed(e.to_string()))?;
+    Ok(cid::Cid::new_v1(0x71, mh).to_string())
+}
+
+/// The DID a signed genesis operation creates: the first 24 characters of the base32 SHA-256 of
+/// its DAG-CBOR encoding.
+fn genesis_did(operation: &serde_json::Value) -> String {
+    let hash = data_encoding::BASE32_NOPAD
+        .encode(&sha256(&encode(operation)))
+        .to_lowercase();
+    format!("did:plc:{}", &hash[..24])
+}
+
+/// The keys allowed to sign the operation after this one.
+fn rotation_keys(operation: &crate::directory::Operation) -> Vec<&str> {
+    match operation {
+        crate::directory::Operation::PlcOperation(operation) => {
+            operation.rotation_keys.iter().map(|v| v.as_str()).collect()
+        }
+        // Legacy genesis operations are read as a plc_operation with these rotation keys.
+        crate::directory::Operation::Create(create) => {
+            vec![&create.recovery_key, &create.signing_key]
+        }
+        crate::directory::Operation::PlcTombstone(_) => vec![],
+    }
+}
+
+/// Whether `sig` is a low-S ECDSA signature of `msg` by the did:key `key`, on either of the
+/// curves atproto allows.
+fn verify_signature(key: &str, msg: &[u8], sig: &[u8]) -> bool {
+    use k256::ecdsa::signature::Verifier;
+
+    let key = match key
+        .strip_prefix("did:key:")
+        .and_then(|key| multibase::decode(key).ok())
+    {
+        Some((_, key)) => key,
+        None => {
+            return false;
+        }
+    };
+    match key.as_slice() {
+        // secp256k1-pub
+        [0xe7, 0x01, key @ ..] => {
+            let (Ok(key), Ok(sig)) = (
+                k256::ecdsa::VerifyingKey::from_sec1_bytes(key),
+                k256::ecdsa::Signature::from_slice(sig),
+            ) else {
+                return false;
+            };
+            sig.normalize_s().is_none() && key.verify(msg, &sig).is_ok()
+        }
+        // p256-pub
+        [0x80, 0x24, key @ ..] => {
+            let (Ok(key), Ok(sig)) = (
+                p256::ecdsa::VerifyingKey::from_sec1_bytes(key),
+                p256::ecdsa::Signature::from_slice(sig),
+            ) else {
+                return false;
+            };
+            sig.normalize_s().is_none() && key.verify(msg, &sig).is_ok()
+        }
+        _ => false,
+    }
+}
+
+/// The index in `keys` of the rotation key that signed `operation`, or `None` if none of them did.
+fn find_signer(keys: &[&str], operation: &serde_json::Value) -> Result<Option<usize>, Failure> {
+    let mut unsigned = operation.clone();
+    let sig = match unsigned
+        .as_object_mut()
+        .and_then(|operation| operation.remove("sig"))
+    {
+        Some(serde_json::Value::String(sig)) => sig,
+        _ => {
+            return Err(Failure::Malformed("missing sig".to_string()));
+        }
+    };
+    let Ok(sig) = data_encoding::BASE64URL_NOPAD.decode(sig.as_bytes()) else {
+        return Err(Failure::Malformed("sig is not base64url".to_string()));
+    };
+    let msg = encode(&unsigned);
+
+    Ok(keys
+        .iter()
+        .position(|key| verify_signature(key, &msg, &sig)))
+}
+
+/// How long after an operation a rotation key of higher priority can still nullify it.
+const RECOVERY_WINDOW: time::Duration = time::Duration::hours(72);
+
+/// An operation whose prev is already followed by another operation forks the log, nullifying that
+/// operation and those after it. It may only do so within [`RECOVERY_WINDOW`] of the operation
+/// following prev, and only if it is signed by a rotation key of prev that comes before the one
+/// that signed that operation.
+///
+/// Signers are indexes into prev's rotation keys. A nullified operation not signed by any of them
+/// can be overridden by any.
+fn check_fork(
+    created_at: time::OffsetDateTime,
+    signer: usize,
+    nullified: &str,
+    nullified_created_at: time::OffsetDateTime,
+    nullified_signer: Option<usize>,
+) -> Result<(), Failure> {
+    if created_at - nullified_created_at > RECOVERY_WINDOW {
+        return Err(Failure::RecoveryWindowClosed {
+            nullified: nullified.to_string(),
+        });
+    }
+    if nullified_signer.is_some_and(|nullified_signer| signer >= nullified_signer) {
+        return Err(Failure::NotHigherPriority {
+            nullified: nullified.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks an entry's CID, that its prev is in the log (or for a genesis operation, that it
+/// creates the entry's DID), that it is signed by one of the rotation keys of its prev, and if it
+/// forks the log, that it is allowed to.
+///
+/// Database errors are returned in the outer result, verification failures in the inner one.
+pub async fn check(
+    conn: &mut sqlx::postgres::PgConnection,
+    entry: &crate::directory::Entry,
+    operation: &crate::directory::Operation,
+    created_at: time::OffsetDateTime,
+) -> Result<Result<(), Failure>, sqlx::Error> {
+    let computed = match cid(&entry.operation) {
+        Ok(computed) => computed,
+        Err(failure) => {
+            return Ok(Err(failure));
+        }
+    };
+    if computed != entry.cid {
+        return Ok(Err(Failure::CidMismatch {
+            claimed: entry.cid.clone(),
+            computed,
+        }));
+    }
+
+    let prev_operation = match operation.prev() {
+        None => {
+            let computed = genesis_did(&entry.operation);
+            if computed != entry.did {
+                return Ok(Err(Failure::DidMismatch { computed }));
+            }
+            None
+        }
+        Some(prev) => {
+            let row = sqlx::query!(
+                r#"--sql
+                SELECT operation
+                FROM plc.operations
+                WHERE did = $1 AND cid = $2
+                "#,
+                entry.did,
+                prev
+            )
+            .fetch_optional(&mut *conn)
+            .await?;
+            let Some(row) = row else {
+                return Ok(Err(Failure::MissingPrev(prev.to_string())));
+            };
+            match serde_json::from_value::<crate::directory::Operation>(row.operation) {
+                Ok(crate::directory::Operation::PlcTombstone(_)) => {
+                    return Ok(Err(Failure::PrevTombstone(prev.to_string())));
+                }
+                Ok(prev_operation) => Some(prev_operation),
+                Err(e) => {
+                    return Ok(Err(Failure::Malformed(e.to_string())));
+                }
+            }
+        }
+    };
+    // A genesis operation is signed by one of its own rotation keys.
+    let keys = rotation_keys(prev_operation.as_ref().unwrap_or(operation));
+
+    let signer = match find_signer(&keys, &entry.operation) {
+        Ok(Some(signer)) => signer,
+        Ok(None) => {
+            return Ok(Err(Failure::BadSignature));
+        }
+        Err(failure) => {
+            return Ok(Err(failure));
+        }
+    };
+
+    let Some(prev) = operation.prev() else {
+        return Ok(Ok(()));
+    };
+    if entry.nullified {
+        // It doesn't nullify anything in turn.
+        return Ok(Ok(()));
+    }
+    // The log forks if something else already follows prev. Whether that is marked nullified
+    // doesn't matter: an audit log marks what the entry itself nullified.
+    let sibling = sqlx::query!(
+        r#"--sql
+        SELECT cid, operation, created_at
+        FROM plc.operations
+        WHERE
+            did = $1 AND
+            prev = $3 AND
+            cid != $2 AND
+            created_at < $4
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+        entry.did,
+        entry.cid,
+        prev,
+        created_at
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(sibling) = sibling else {
+        return Ok(Ok(()));
+    };
+    let sibling_signer = find_signer(&keys, &sibling.operation).unwrap_or_default();
+    Ok(check_fork(
+        created_at,
+        signer,
+        &sibling.cid,
+        sibling.created_at,
+        sibling_signer,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    // Signed with fixed keys by a separate implementation: Python's cryptography package and a
+    // hand-written DAG-CBOR encoder. The rotation keys are a P-256 key, then a secp256k1 one.
+
+    fn genesis() -> serde_json::Value {
+        serde_json::json!({
+            "type": "plc_operation",
+            "rotationKeys": [
+                "did:key:zDnaereBVxtJJRK9P83QFpbwhBX9LASDs6btPczwrsBDf8jQ4",
+                "did:key:zQ3shVKkApr5GSSJvViPWCwcqHttkoSzfyDE7iPioeK6QdHre"
+            ],
+            "verificationMethods": {
+                "atproto": "did:key:zQ3shcrUm3TMhgJqryRruES9PcspdPC6frcwbnfz5ASQ8YbPp"
+            },
+            "alsoKnownAs": [
+                "at://alice.test"
+            ],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.test"
+                }
+            },
+            "prev": null,
+            "sig": "8OuQ2AiVHdqQuOdCzZ2omPzx4wDDELJHr_cK6uHdJ5Za7GUJW_0ns4gMkgbD7JEFTyd8LeJJTu1N-H4w2nP1eQ"
+        })
+    }
+
+    fn update() -> serde_json::Value {
+        serde_json::json!({
+            "type": "plc_operation",
+            "rotationKeys": [
+                "did:key:zDnaereBVxtJJRK9P83QFpbwhBX9LASDs6btPczwrsBDf8jQ4",
+                "did:key:zQ3shVKkApr5GSSJvViPWCwcqHttkoSzfyDE7iPioeK6QdHre"
+            ],
+            "verificationMethods": {
+                "atproto": "did:key:zQ3shcrUm3TMhgJqryRruES9PcspdPC6frcwbnfz5ASQ8YbPp"
+            },
+            "alsoKnownAs": [
+                "at://alice2.test"
+            ],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.test"
+                }
+            },
+            "prev": "bafyreid7m5em6ttdaayndevo2fk76ajxdbrjtkjkt7qm3bul4i5zdbulpq",
+            "sig": "_AaFLCB6kM1ZfNXngrLEmVIngT-WsAr8kSejVYmGzdkYz52bPz33M0wcevwjGlXOSPSVE4rwGu2pCQuw7_Hk2Q"
+        })
+    }
+
+    fn create() -> serde_json::Value {
+        serde_json::json!({
+            "type": "create",
+            "signingKey": "did:key:zQ3shcrUm3TMhgJqryRruES9PcspdPC6frcwbnfz5ASQ8YbPp",
+            "recoveryKey": "did:key:zQ3shVKkApr5GSSJvViPWCwcqHttkoSzfyDE7iPioeK6QdHre",
+            "handle": "bob.test",
+            "service": "https://pds.test",
+            "prev": null,
+            "sig": "ZhPq0vWoyVL49cykXfvuddCnO7blApX9DRf25deuXHsf8Z-YFGgGIMRo2jSnV19IwfDKgspbUTgHzhT4acVbmA"
+        })
+    }
+
+    fn keys(operation: &serde_json::Value) -> Vec<String> {
+        let operation: crate::directory::Operation =
+            serde_json::from_value(operation.clone()).unwrap();
+        super::rotation_keys(&operation)
+            .into_iter()
+            .map(|key| key.to_string())
+            .collect()
+    }
+
+    fn find_signer(keys: &[String], operation: &serde_json::Value) -> Option<usize> {
+        let keys = keys.iter().map(|key| key.as_str()).collect::<Vec<_>>();
+        super::find_signer(&keys, operation).unwrap()
+    }
+
+    #[test]
+    fn map_keys_are_canonically_ordered() {
+        let value = serde_json::json!({"cc": [true, null], "b": 2, "aaa": 1, "a": "x"});
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(&super::encode(&value)),
+            "a46161617861620262636382f5f66361616101"
+        );
+    }
+
+    #[test]
+    fn cid() {
+        assert_eq!(
+            super::cid(&genesis()).unwrap(),
+            "bafyreid7m5em6ttdaayndevo2fk76ajxdbrjtkjkt7qm3bul4i5zdbulpq"
+        );
+        assert_eq!(
+            super::cid(&create()).unwrap(),
+            "bafyreicmdp3hsj2kb6gl2nd5aunvnbo6vrjjghzu63uo6nde56kzqjsjxq"
+        );
+    }
+
+    #[test]
+    fn genesis_did() {
+        assert_eq!(
+            super::genesis_did(&genesis()),
+            "did:plc:p5turt2ommadbumsv3ivl7yb"
+        );
+        assert_eq!(
+            super::genesis_did(&create()),
+            "did:plc:jqn7m6jhjihyzpjupucrwvuf"
+        );
+    }
+
+    #[test]
+    fn signed() {
+        // The genesis operation is signed by its secp256k1 key, the update by the P-256 one.
+        assert_eq!(find_signer(&keys(&genesis()), &genesis()), Some(1));
+        assert_eq!(find_signer(&keys(&genesis()), &update()), Some(0));
+        // A legacy create is signed by its signing key, which comes after its recovery key.
+        assert_eq!(find_signer(&keys(&create()), &create()), Some(1));
+    }
+
+    #[test]
+    fn tampered() {
+        let mut operation = update();
+        operation["alsoKnownAs"] = serde_json::json!(["at://mallory.test"]);
+        assert_eq!(find_signer(&keys(&genesis()), &operation), None);
+    }
+
+    #[test]
+    fn high_s() {
+        // The same signatures with S replaced by N - S, which verify but aren't canonical.
+        let mut operation = update();
+        operation["sig"] = "_AaFLCB6kM1ZfNXngrLEmVIngT-WsAr8kSejVYmGzdnnMGJjwMIIzbPjhQPc5aoxc_Jlmhwng5dKsL8SDHFAeA".into();
+        assert_eq!(find_signer(&keys(&genesis()), &operation), None);
+
+        let mut operation = create();
+        operation["sig"] = "ZhPq0vWoyVL49cykXfvuddCnO7blApX9DRf25deuXHvgDmBn65f53zuXJctYqKC1-L4SY-TtTwO4BEmUZnDlqQ".into();
+        assert_eq!(find_signer(&keys(&create()), &operation), None);
+    }
+
+    #[test]
+    fn fork() {
+        let nullified_created_at =
+            time::OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap();
+        let check = |hours, signer, nullified_signer| {
+            super::check_fork(
+                nullified_created_at + time::Duration::hours(hours),
+                signer,
+                "nullified",
+                nullified_created_at,
+                nullified_signer,
+            )
+            .map_err(|failure| failure.reason())
+        };
+
+        assert_eq!(check(1, 0, Some(1)), Ok(()));
+        assert_eq!(check(72, 0, Some(1)), Ok(()));
+        assert_eq!(check(73, 0, Some(1)), Err("recovery_window_closed"));
+        assert_eq!(check(1, 1, Some(1)), Err("not_higher_priority"));
+        assert_eq!(check(1, 1, Some(0)), Err("not_higher_priority"));
+        assert_eq!(check(1, 1, None), Ok(()));
+    }
+}
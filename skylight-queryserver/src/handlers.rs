@@ -1,4 +1,6 @@
 mod akas;
+mod handles;
+mod history;
 mod incoming;
 mod mutuals;
 mod neighborhood;
@@ -6,6 +8,8 @@ mod paths;
 mod whois;
 
 pub use akas::akas;
+pub use handles::handles;
+pub use history::history;
 pub use incoming::incoming;
 pub use mutuals::mutuals;
 pub use neighborhood::neighborhood;
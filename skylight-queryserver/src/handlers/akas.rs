@@ -17,8 +17,8 @@ pub async fn akas(
     Ok(axum::response::Json(Response {
         akas: sqlx::query!(
             r#"--sql
-            SELECT did, also_known_as
-            FROM plc.dids
+            SELECT did AS "did!", also_known_as AS "also_known_as!"
+            FROM plc.identities
             WHERE did = ANY($1)
             "#,
             &req.did
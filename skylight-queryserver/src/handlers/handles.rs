@@ -0,0 +1,50 @@
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    did: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    handles: Vec<Entry>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    handle: String,
+    cid: String,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: time::OffsetDateTime,
+}
+
+/// The handles a DID has claimed in its PLC operation log, oldest first.
+pub async fn handles(
+    axum::extract::State(state): axum::extract::State<std::sync::Arc<crate::AppState>>,
+    crate::query::Query(req): crate::query::Query<Request>,
+) -> Result<axum::response::Json<Response>, crate::error::Error> {
+    Ok(axum::response::Json(Response {
+        handles: sqlx::query!(
+            r#"--sql
+            SELECT
+                handle AS "handle!",
+                cid AS "cid!",
+                created_at AS "created_at!"
+            FROM plc.handle_history
+            WHERE did = $1
+            ORDER BY created_at
+            "#,
+            req.did
+        )
+        .fetch_all(&state.pool)
+        .await?
+        .into_iter()
+        .map(|r| Entry {
+            handle: r.handle,
+            cid: r.cid,
+            created_at: r.created_at,
+        })
+        .collect(),
+    }))
+}
@@ -0,0 +1,99 @@
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    actor_did: String,
+    subject_did: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    history: Vec<Entry>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    rkey: String,
+    #[serde(with = "time::serde::rfc3339::option")]
+    created_at: Option<time::OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339")]
+    first_seen: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    last_seen: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    deleted_at: Option<time::OffsetDateTime>,
+}
+
+pub async fn history(
+    axum::extract::State(state): axum::extract::State<std::sync::Arc<crate::AppState>>,
+    crate::query::Query(req): crate::query::Query<Request>,
+) -> Result<axum::response::Json<Response>, crate::error::Error> {
+    let input_ids = crate::ids::get_ids_for_dids(
+        &state.pool,
+        &[req.actor_did.clone(), req.subject_did.clone()],
+    )
+    .await?;
+
+    let (actor_id, subject_id) = match (
+        input_ids.get(&req.actor_did).cloned(),
+        input_ids.get(&req.subject_did).cloned(),
+    ) {
+        (Some(actor_id), Some(subject_id)) => (actor_id, subject_id),
+        _ => {
+            return Err(crate::error::Error::status(
+                axum::http::StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+
+    Ok(axum::response::Json(Response {
+        history: sqlx::query!(
+            r#"--sql
+            SELECT
+                rkey AS "rkey!",
+                created_at,
+                first_seen AS "first_seen!",
+                last_seen AS "last_seen!",
+                deleted_at
+            FROM (
+                SELECT
+                    rkey,
+                    created_at,
+                    first_seen,
+                    last_seen,
+                    NULL::TIMESTAMPTZ AS deleted_at
+                FROM follows.visible_edges
+                WHERE
+                    actor_id = $1 AND
+                    subject_id = $2
+                UNION ALL
+                SELECT rkey, created_at, first_seen, last_seen, deleted_at
+                FROM follows.edges_history
+                WHERE
+                    actor_id = $1 AND
+                    subject_id = $2 AND
+                    NOT EXISTS (
+                        SELECT *
+                        FROM follows.accounts
+                        WHERE id IN ($1, $2) AND NOT active
+                    )
+            ) AS e
+            ORDER BY first_seen
+            "#,
+            actor_id,
+            subject_id
+        )
+        .fetch_all(&state.pool)
+        .await?
+        .into_iter()
+        .map(|r| Entry {
+            rkey: r.rkey,
+            created_at: r.created_at,
+            first_seen: r.first_seen,
+            last_seen: r.last_seen,
+            deleted_at: r.deleted_at,
+        })
+        .collect(),
+    }))
+}
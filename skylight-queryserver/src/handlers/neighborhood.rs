@@ -4,6 +4,9 @@ pub struct Request {
     did: Vec<String>,
     #[serde(default)]
     ignore_did: Vec<String>,
+    /// Compute the neighborhood as it was at this time, from the edge history, instead of now.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    at: Option<time::OffsetDateTime>,
 }
 
 #[derive(serde::Serialize)]
@@ -36,17 +39,35 @@ pub async fn neighborhood(
         .cloned()
         .collect::<Vec<_>>();
 
-    let n = sqlx::query!(
-        r#"--sql
-        SELECT COUNT(*) AS "count!"
-        FROM follows.edges
-        WHERE actor_id = ANY($1)
-        "#,
-        &ids,
-    )
-    .fetch_one(&state.pool)
-    .await?
-    .count;
+    let n = match req.at {
+        None => {
+            sqlx::query!(
+                r#"--sql
+                SELECT COUNT(*) AS "count!"
+                FROM follows.visible_edges
+                WHERE actor_id = ANY($1)
+                "#,
+                &ids,
+            )
+            .fetch_one(&state.pool)
+            .await?
+            .count
+        }
+        Some(at) => {
+            sqlx::query!(
+                r#"--sql
+                SELECT COUNT(*) AS "count!"
+                FROM follows.visible_edges_as_of($2)
+                WHERE actor_id = ANY($1)
+                "#,
+                &ids,
+                at
+            )
+            .fetch_one(&state.pool)
+            .await?
+            .count
+        }
+    };
 
     const MAX_FOLLOWS: i64 = 3000;
     if n > MAX_FOLLOWS {
@@ -59,33 +80,48 @@ pub async fn neighborhood(
         .flat_map(|did| input_ids.get(&did).cloned())
         .collect::<Vec<_>>();
 
-    let rows = sqlx::query!(
-        r#"--sql
-        SELECT actor_id as "actor_id!", subject_ids as "subject_ids!"
-        FROM follows.neighborhood($1, $2)
-        "#,
-        &ids,
-        &ignore_ids
-    )
-    .fetch_all(&state.pool)
-    .await?;
+    let rows = match req.at {
+        None => sqlx::query!(
+            r#"--sql
+            SELECT actor_id as "actor_id!", subject_ids as "subject_ids!"
+            FROM follows.neighborhood($1, $2)
+            "#,
+            &ids,
+            &ignore_ids
+        )
+        .fetch_all(&state.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.actor_id, row.subject_ids))
+        .collect::<Vec<_>>(),
+        Some(at) => sqlx::query!(
+            r#"--sql
+            SELECT actor_id as "actor_id!", subject_ids as "subject_ids!"
+            FROM follows.neighborhood_as_of($1, $2, $3)
+            "#,
+            &ids,
+            &ignore_ids,
+            at
+        )
+        .fetch_all(&state.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.actor_id, row.subject_ids))
+        .collect::<Vec<_>>(),
+    };
 
     let output_dids = crate::ids::get_dids_for_ids(
         &state.pool,
         &rows
             .iter()
-            .flat_map(|row| {
-                [row.actor_id]
-                    .into_iter()
-                    .chain(row.subject_ids.iter().cloned())
-            })
+            .flat_map(|row| [row.0].into_iter().chain(row.1.iter().cloned()))
             .collect::<Vec<_>>(),
     )
     .await?;
 
     let node_to_index = rows
         .iter()
-        .map(|row| row.actor_id)
+        .map(|row| row.0)
         .enumerate()
         .map(|(k, v)| (v, k))
         .collect::<std::collections::HashMap<i32, usize>>();
@@ -95,15 +131,15 @@ pub async fn neighborhood(
             .iter()
             .map(|row| {
                 output_dids
-                    .get(&row.actor_id)
+                    .get(&row.0)
                     .cloned()
-                    .ok_or_else(|| anyhow::format_err!("unknown id: {}", row.actor_id))
+                    .ok_or_else(|| anyhow::format_err!("unknown id: {}", row.0))
             })
             .collect::<Result<Vec<_>, _>>()?,
         edges: rows
             .iter()
             .map(|row| {
-                row.subject_ids
+                row.1
                     .iter()
                     .flat_map(|n| node_to_index.get(n).cloned())
                     .collect()
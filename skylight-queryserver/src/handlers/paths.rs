@@ -12,6 +12,7 @@ fn paths_stream(
     target_id: i32,
     ignore_ids: Vec<i32>,
     mut conn: sqlx::pool::PoolConnection<sqlx::Postgres>,
+    shutdown: tokio::sync::watch::Receiver<bool>,
 ) -> impl futures_util::stream::Stream<Item = Result<std::string::String, anyhow::Error>> {
     let mut path_dids = std::collections::HashMap::new();
 
@@ -28,6 +29,11 @@ fn paths_stream(
         .await?;
 
         loop {
+            // Stop searching for more paths so the response can finish during shutdown.
+            if *shutdown.borrow() {
+                break;
+            }
+
             const LIMIT: i32 = 1;
             let rows = sqlx::query!(
                 r#"--sql
@@ -107,6 +113,12 @@ pub async fn paths(
     let conn = state.pool.acquire().await?;
     Ok((
         headers,
-        axum::body::StreamBody::new(paths_stream(source_id, target_id, ignore_ids, conn)),
+        axum::body::StreamBody::new(paths_stream(
+            source_id,
+            target_id,
+            ignore_ids,
+            conn,
+            state.shutdown.clone(),
+        )),
     ))
 }
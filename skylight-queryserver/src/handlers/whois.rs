@@ -15,6 +15,8 @@ pub struct Response {
 pub struct Entry {
     did: String,
     also_known_as: Vec<String>,
+    /// The endpoint of the PDS hosting the account, if known.
+    pds: Option<String>,
 }
 
 pub async fn whois(
@@ -24,18 +26,34 @@ pub async fn whois(
     Ok(axum::response::Json(Response {
         whois: sqlx::query!(
             r#"--sql
-            SELECT a.actor AS "actor!", did, also_known_as
+            SELECT
+                a.actor AS "actor!",
+                i.did AS "did!",
+                i.also_known_as AS "also_known_as!",
+                d.pds_endpoint AS "pds?"
             FROM UNNEST($1::TEXT []) AS a(actor)
-            INNER JOIN plc.dids ON
+            INNER JOIN plc.identities AS i ON
+                i.did IN (
+                    SELECT did
+                    FROM plc.dids
+                    WHERE
+                        did = a.actor OR
+                        also_known_as && ARRAY[a.actor, 'at://' || a.actor]
+                    UNION
+                    SELECT did
+                    FROM plc.handles
+                    WHERE did = a.actor OR handle = a.actor
+                ) AND
                 (
-                    did = a.actor OR
-                    also_known_as && ARRAY[a.actor, 'at://' || a.actor]
+                    i.did = a.actor OR
+                    i.also_known_as && ARRAY[a.actor, 'at://' || a.actor]
                 ) AND
                 EXISTS (
                     SELECT *
                     FROM follows.dids
-                    WHERE follows.dids.did = plc.dids.did
+                    WHERE follows.dids.did = i.did
                 )
+            LEFT JOIN plc.dids AS d ON i.did = d.did
             "#,
             &req.actor
         )
@@ -48,6 +66,7 @@ pub async fn whois(
                 Entry {
                     did: r.did,
                     also_known_as: r.also_known_as,
+                    pds: r.pds,
                 },
             )
         })
@@ -13,23 +13,36 @@ struct Args {
 
     #[arg(long, default_value = "postgres:///skylight")]
     dsn: String,
+
+    /// Apply pending schema migrations before starting. Without this, the binary refuses to run
+    /// against a database whose schema isn't the version it was built against.
+    #[arg(long, default_value_t = false)]
+    migrate: bool,
+
+    /// Seconds to let in-flight responses drain after SIGTERM.
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout: u64,
 }
 
 pub struct AppState {
     pool: sqlx::pool::Pool<sqlx::Postgres>,
+    shutdown: tokio::sync::watch::Receiver<bool>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(tracing::Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    skylight_common::logging::init();
 
     let args = Args::parse();
 
-    let pool = sqlx::postgres::PgPool::connect(&args.dsn).await?;
-    let app_state = std::sync::Arc::new(AppState { pool });
+    let mut shutdown_rx = skylight_common::shutdown::watch();
+
+    let (conn_options, _) = skylight_common::db::connect(&args.dsn, args.migrate).await?;
+    let pool = sqlx::postgres::PgPool::connect_with(conn_options).await?;
+    let app_state = std::sync::Arc::new(AppState {
+        pool,
+        shutdown: shutdown_rx.clone(),
+    });
 
     let app = axum::Router::new().nest(
         "/_",
@@ -40,13 +53,30 @@ async fn main() -> Result<(), anyhow::Error> {
             .route("/incoming", axum::routing::get(handlers::incoming))
             .route("/neighborhood", axum::routing::get(handlers::neighborhood))
             .route("/paths", axum::routing::get(handlers::paths))
+            .route("/history", axum::routing::get(handlers::history))
+            .route("/handles", axum::routing::get(handlers::handles))
             .with_state(app_state),
     );
 
     let listener = tokio::net::TcpListener::bind(&args.listen).await?;
     tracing::info!(listen = ?listener.local_addr()? );
-    axum::Server::builder(hyper::server::conn::AddrIncoming::from_listener(listener)?)
+    let server = axum::Server::builder(hyper::server::conn::AddrIncoming::from_listener(listener)?)
         .serve(app.into_make_service())
-        .await?;
+        .with_graceful_shutdown({
+            let mut shutdown_rx = shutdown_rx.clone();
+            async move {
+                let _ = shutdown_rx.wait_for(|v| *v).await;
+            }
+        });
+
+    tokio::select! {
+        r = server => r?,
+        _ = async {
+            let _ = shutdown_rx.wait_for(|v| *v).await;
+            tokio::time::sleep(std::time::Duration::from_secs(args.shutdown_timeout)).await;
+        } => {
+            tracing::error!("timed out draining connections");
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,78 @@
+//! A stand-in for a relay and PDS on localhost, for testing the crawler and ingester without a
+//! network.
+
+use clap::Parser;
+
+mod repos;
+mod script;
+mod subscribe;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:2583")]
+    listen: std::net::SocketAddr,
+
+    /// Script of frames to send to com.atproto.sync.subscribeRepos subscribers. See script.rs for
+    /// the format.
+    #[arg(long)]
+    script: Option<std::path::PathBuf>,
+
+    /// Directory of `<did>.car` files to serve from com.atproto.sync.getRepo and listRepos.
+    #[arg(long)]
+    repos_dir: Option<std::path::PathBuf>,
+}
+
+pub struct AppState {
+    events: Vec<script::Event>,
+    progress: std::sync::Mutex<subscribe::Progress>,
+    repos: std::collections::BTreeMap<String, repos::Repo>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    skylight_common::logging::init();
+
+    let args = Args::parse();
+
+    let events = match &args.script {
+        Some(path) => script::load(path).await?,
+        None => vec![],
+    };
+    let repos = match &args.repos_dir {
+        Some(dir) => repos::load(dir).await?,
+        None => std::collections::BTreeMap::new(),
+    };
+    tracing::info!(events = events.len(), repos = repos.len(), "loaded");
+
+    let app_state = std::sync::Arc::new(AppState {
+        events,
+        progress: std::sync::Mutex::new(subscribe::Progress::default()),
+        repos,
+    });
+
+    let app = axum::Router::new()
+        .route(
+            "/xrpc/com.atproto.sync.subscribeRepos",
+            axum::routing::get(subscribe::subscribe),
+        )
+        .route(
+            "/xrpc/com.atproto.sync.getRepo",
+            axum::routing::get(repos::get_repo),
+        )
+        .route(
+            "/xrpc/com.atproto.sync.listRepos",
+            axum::routing::get(repos::list_repos),
+        )
+        // Jetstream's endpoint, for scripts of Jetstream events.
+        .route("/subscribe", axum::routing::get(subscribe::subscribe))
+        .with_state(app_state);
+
+    let listener = tokio::net::TcpListener::bind(&args.listen).await?;
+    tracing::info!(listen = ?listener.local_addr()? );
+    axum::Server::builder(hyper::server::conn::AddrIncoming::from_listener(listener)?)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(skylight_common::shutdown::signal())
+        .await?;
+    Ok(())
+}
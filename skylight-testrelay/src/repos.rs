@@ -0,0 +1,156 @@
+/// A repo served from a CAR file named after its DID, e.g. `did:plc:abc.car`.
+pub struct Repo {
+    pub head: String,
+    pub path: std::path::PathBuf,
+    pub rev: Option<String>,
+}
+
+/// Reads the head and rev of every CAR file in `dir`, keyed by DID.
+pub async fn load(
+    dir: &std::path::Path,
+) -> Result<std::collections::BTreeMap<String, Repo>, anyhow::Error> {
+    #[derive(serde::Deserialize)]
+    struct Commit {
+        rev: Option<String>,
+    }
+
+    let mut repos = std::collections::BTreeMap::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let did = match (path.extension(), path.file_stem()) {
+            (Some(ext), Some(stem)) if ext == "car" => stem.to_string_lossy().into_owned(),
+            _ => {
+                continue;
+            }
+        };
+
+        let buf = tokio::fs::read(&path).await?;
+        let blocks = atproto_repo::blockstore::Loader::new()
+            .load_partial(&mut &buf[..])
+            .await?;
+        let head = *blocks
+            .roots()
+            .first()
+            .ok_or(atproto_repo::blockstore::Error::NoRoots)?;
+        let commit: Option<Commit> = blocks.get_record(&head)?;
+
+        repos.insert(
+            did,
+            Repo {
+                head: head.to_string(),
+                path,
+                rev: commit.and_then(|commit| commit.rev),
+            },
+        );
+    }
+    Ok(repos)
+}
+
+/// An XRPC error response.
+fn xrpc_error(
+    status: axum::http::StatusCode,
+    error: &str,
+    message: String,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    #[derive(serde::Serialize)]
+    struct Body<'a> {
+        error: &'a str,
+        message: String,
+    }
+
+    (status, axum::response::Json(Body { error, message })).into_response()
+}
+
+#[derive(serde::Deserialize)]
+pub struct GetRepoRequest {
+    did: String,
+}
+
+pub async fn get_repo(
+    axum::extract::State(state): axum::extract::State<std::sync::Arc<crate::AppState>>,
+    axum::extract::Query(req): axum::extract::Query<GetRepoRequest>,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let repo = if let Some(repo) = state.repos.get(&req.did) {
+        repo
+    } else {
+        return xrpc_error(
+            axum::http::StatusCode::BAD_REQUEST,
+            "RepoNotFound",
+            format!("Could not find repo for DID: {}", req.did),
+        );
+    };
+
+    match tokio::fs::read(&repo.path).await {
+        Ok(buf) => (
+            [(axum::http::header::CONTENT_TYPE, "application/vnd.ipld.car")],
+            buf,
+        )
+            .into_response(),
+        Err(e) => xrpc_error(
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            "InternalServerError",
+            e.to_string(),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListReposRequest {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListReposResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+    repos: Vec<ListReposEntry>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListReposEntry {
+    active: bool,
+    did: String,
+    head: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+}
+
+pub async fn list_repos(
+    axum::extract::State(state): axum::extract::State<std::sync::Arc<crate::AppState>>,
+    axum::extract::Query(req): axum::extract::Query<ListReposRequest>,
+) -> axum::response::Json<ListReposResponse> {
+    let limit = req.limit.unwrap_or(500).clamp(1, 1000);
+
+    let repos = match &req.cursor {
+        Some(cursor) => state.repos.range::<String, _>((
+            std::ops::Bound::Excluded(cursor),
+            std::ops::Bound::Unbounded,
+        )),
+        None => state.repos.range::<String, _>(..),
+    }
+    .take(limit)
+    .map(|(did, repo)| ListReposEntry {
+        active: true,
+        did: did.clone(),
+        head: repo.head.clone(),
+        rev: repo.rev.clone(),
+    })
+    .collect::<Vec<_>>();
+
+    axum::response::Json(ListReposResponse {
+        cursor: if repos.len() == limit {
+            repos.last().map(|repo| repo.did.clone())
+        } else {
+            None
+        },
+        repos,
+    })
+}
@@ -0,0 +1,245 @@
+//! Scripts of what to send to firehose subscribers.
+//!
+//! A script is a JSON lines file, one step per line:
+//!
+//! - `{"message": {"type": "#commit", "body": {...}, "blocks": "repo.car"}}` sends a message frame.
+//!   Objects of the form `{"$link": "bafy..."}` in the body are encoded as CIDs, and `blocks`, if
+//!   given, names a CAR file whose bytes become the body's `blocks`.
+//! - `{"error": {"error": "FutureCursor", "message": "..."}}` sends an error frame (`op == -1`)
+//!   and closes the connection.
+//! - `{"jetstream": {...}}` sends a Jetstream event as a text frame.
+//! - `{"recording": "frames.bin"}` sends the frames of a recording made with the ingester's
+//!   `--record-dir`.
+//! - `"disconnect"` closes the connection.
+//! - `{"sleep_ms": 100}` pauses before the next step.
+//!
+//! Errors and disconnects only happen once, so that a client reconnecting past them makes
+//! progress. Relative paths are resolved against the script's directory.
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    Message {
+        r#type: String,
+        body: serde_json::Value,
+        blocks: Option<std::path::PathBuf>,
+    },
+    Error {
+        error: String,
+        message: Option<String>,
+    },
+    Jetstream(serde_json::Value),
+    Recording(std::path::PathBuf),
+    Disconnect,
+    SleepMs(u64),
+}
+
+pub enum Event {
+    /// A data frame, with the seq it can be resumed after: the firehose seq, or a Jetstream
+    /// event's time in microseconds.
+    Frame {
+        seq: Option<i64>,
+        msg: axum::extract::ws::Message,
+    },
+    Error(Vec<u8>),
+    Disconnect,
+    Sleep(std::time::Duration),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("cbor: {0}")]
+    CborWrite(#[from] ciborium::ser::Error<std::io::Error>),
+
+    #[error("cbor: {0}")]
+    CborRead(#[from] ciborium::de::Error<std::io::Error>),
+
+    #[error("cid: {0}")]
+    Cid(#[from] cid::Error),
+
+    #[error("line {0}: {1}")]
+    Line(usize, Box<Error>),
+
+    #[error("recording: {0}")]
+    Recording(#[from] skylight_common::recording::Error),
+
+    #[error("body is not an object")]
+    BodyNotObject,
+}
+
+#[derive(serde::Serialize)]
+struct Header<'a> {
+    op: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    t: Option<&'a str>,
+}
+
+/// Converts JSON to CBOR, encoding `{"$link": ...}` objects as CIDs.
+fn to_cbor(value: serde_json::Value) -> Result<ciborium::value::Value, Error> {
+    Ok(match value {
+        serde_json::Value::Null => ciborium::value::Value::Null,
+        serde_json::Value::Bool(v) => ciborium::value::Value::Bool(v),
+        serde_json::Value::Number(v) => {
+            if let Some(v) = v.as_i64() {
+                ciborium::value::Value::Integer(v.into())
+            } else if let Some(v) = v.as_u64() {
+                ciborium::value::Value::Integer(v.into())
+            } else {
+                ciborium::value::Value::Float(v.as_f64().unwrap_or_default())
+            }
+        }
+        serde_json::Value::String(v) => ciborium::value::Value::Text(v),
+        serde_json::Value::Array(v) => {
+            ciborium::value::Value::Array(v.into_iter().map(to_cbor).collect::<Result<_, _>>()?)
+        }
+        serde_json::Value::Object(v) => {
+            if let (1, Some(serde_json::Value::String(link))) = (v.len(), v.get("$link")) {
+                // DAG-CBOR CIDs are tag 42 over the binary CID with a multibase identity prefix.
+                let mut buf = vec![0];
+                buf.extend(cid::Cid::try_from(link.as_str())?.to_bytes());
+                return Ok(ciborium::value::Value::Tag(
+                    42,
+                    Box::new(ciborium::value::Value::Bytes(buf)),
+                ));
+            }
+            ciborium::value::Value::Map(
+                v.into_iter()
+                    .map(|(k, v)| Ok((ciborium::value::Value::Text(k), to_cbor(v)?)))
+                    .collect::<Result<_, Error>>()?,
+            )
+        }
+    })
+}
+
+/// Finds the seq of a binary frame, if it has one.
+fn frame_seq(buf: &[u8]) -> Result<Option<i64>, Error> {
+    #[derive(serde::Deserialize)]
+    struct Header {
+        op: i8,
+    }
+    #[derive(serde::Deserialize)]
+    struct Body {
+        seq: Option<i64>,
+    }
+
+    let mut cursor = std::io::Cursor::new(buf);
+    let Header { op } = ciborium::from_reader(&mut cursor)?;
+    if op != 1 {
+        return Ok(None);
+    }
+    let Body { seq } = ciborium::from_reader(&mut cursor)?;
+    Ok(seq)
+}
+
+fn jetstream_seq(text: &str) -> Result<Option<i64>, Error> {
+    #[derive(serde::Deserialize)]
+    struct Event {
+        time_us: Option<i64>,
+    }
+    Ok(serde_json::from_str::<Event>(text)?.time_us)
+}
+
+/// Reads the frames of a recording made with the ingester's `--record-dir`.
+async fn load_recording(path: &std::path::Path) -> Result<Vec<Event>, Error> {
+    let mut r = tokio::io::BufReader::new(tokio::fs::File::open(path).await?);
+    let mut events = vec![];
+    while let Some((_, frame)) = skylight_common::recording::read_frame(&mut r).await? {
+        events.push(match frame {
+            skylight_common::recording::Frame::Binary(buf) => Event::Frame {
+                seq: frame_seq(&buf)?,
+                msg: axum::extract::ws::Message::Binary(buf),
+            },
+            skylight_common::recording::Frame::Text(text) => Event::Frame {
+                seq: jetstream_seq(&text)?,
+                msg: axum::extract::ws::Message::Text(text),
+            },
+        });
+    }
+    Ok(events)
+}
+
+async fn load_step(dir: &std::path::Path, step: Step) -> Result<Vec<Event>, Error> {
+    Ok(match step {
+        Step::Message {
+            r#type,
+            body,
+            blocks,
+        } => {
+            let mut body = to_cbor(body)?;
+            if let Some(blocks) = blocks {
+                let buf = tokio::fs::read(dir.join(blocks)).await?;
+                if let ciborium::value::Value::Map(entries) = &mut body {
+                    entries.push((
+                        ciborium::value::Value::Text("blocks".to_string()),
+                        ciborium::value::Value::Bytes(buf),
+                    ));
+                } else {
+                    return Err(Error::BodyNotObject);
+                }
+            }
+
+            let mut buf = vec![];
+            ciborium::into_writer(
+                &Header {
+                    op: 1,
+                    t: Some(&r#type),
+                },
+                &mut buf,
+            )?;
+            ciborium::into_writer(&body, &mut buf)?;
+            vec![Event::Frame {
+                seq: frame_seq(&buf)?,
+                msg: axum::extract::ws::Message::Binary(buf),
+            }]
+        }
+        Step::Error { error, message } => {
+            #[derive(serde::Serialize)]
+            struct ErrorBody {
+                error: String,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                message: Option<String>,
+            }
+
+            let mut buf = vec![];
+            ciborium::into_writer(&Header { op: -1, t: None }, &mut buf)?;
+            ciborium::into_writer(&ErrorBody { error, message }, &mut buf)?;
+            vec![Event::Error(buf)]
+        }
+        Step::Jetstream(event) => {
+            let text = serde_json::to_string(&event)?;
+            vec![Event::Frame {
+                seq: jetstream_seq(&text)?,
+                msg: axum::extract::ws::Message::Text(text),
+            }]
+        }
+        Step::Recording(path) => load_recording(&dir.join(path)).await?,
+        Step::Disconnect => vec![Event::Disconnect],
+        Step::SleepMs(ms) => vec![Event::Sleep(std::time::Duration::from_millis(ms))],
+    })
+}
+
+pub async fn load(path: &std::path::Path) -> Result<Vec<Event>, Error> {
+    let dir = path.parent().unwrap_or_else(|| std::path::Path::new("."));
+    let script = tokio::fs::read_to_string(path).await?;
+
+    let mut events = vec![];
+    for (i, line) in script.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let step =
+            serde_json::from_str(line).map_err(|e| Error::Line(i + 1, Box::new(e.into())))?;
+        events.extend(
+            load_step(dir, step)
+                .await
+                .map_err(|e| Error::Line(i + 1, Box::new(e)))?,
+        );
+    }
+    Ok(events)
+}
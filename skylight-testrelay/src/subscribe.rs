@@ -0,0 +1,127 @@
+/// Where the script is up to. Subscribers without a cursor pick up where the last one left off,
+/// like a live relay.
+#[derive(Default)]
+pub struct Progress {
+    position: usize,
+    fired: std::collections::HashSet<usize>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Request {
+    cursor: Option<i64>,
+}
+
+/// Streams the script to a subscriber, from just after `cursor` if given.
+pub async fn subscribe(
+    axum::extract::State(state): axum::extract::State<std::sync::Arc<crate::AppState>>,
+    axum::extract::Query(req): axum::extract::Query<Request>,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> axum::response::Response {
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = stream(&state, req.cursor, socket).await {
+            tracing::info!(error = format!("{e:?}"), "subscriber gone");
+        }
+    })
+}
+
+/// Whether a one-shot step at `i` has yet to happen, marking it as having happened.
+fn fire(state: &crate::AppState, i: usize) -> bool {
+    state.progress.lock().unwrap().fired.insert(i)
+}
+
+async fn stream(
+    state: &crate::AppState,
+    cursor: Option<i64>,
+    mut socket: axum::extract::ws::WebSocket,
+) -> Result<(), axum::Error> {
+    let events = &state.events;
+
+    let mut i = match cursor {
+        Some(cursor) => {
+            let max_seq = events
+                .iter()
+                .filter_map(|event| match event {
+                    crate::script::Event::Frame { seq, .. } => *seq,
+                    _ => None,
+                })
+                .max();
+            if max_seq.map(|max_seq| cursor > max_seq).unwrap_or(false) {
+                tracing::info!(cursor, "future cursor");
+                socket
+                    .send(axum::extract::ws::Message::Binary(future_cursor_frame()))
+                    .await?;
+                return socket.close().await;
+            }
+
+            events
+                .iter()
+                .position(|event| match event {
+                    crate::script::Event::Frame { seq: Some(seq), .. } => *seq > cursor,
+                    _ => false,
+                })
+                .unwrap_or(events.len())
+        }
+        None => state.progress.lock().unwrap().position,
+    };
+    tracing::info!(cursor, position = i, "subscriber");
+
+    while i < events.len() {
+        match &events[i] {
+            crate::script::Event::Frame { msg, .. } => {
+                socket.send(msg.clone()).await?;
+            }
+            crate::script::Event::Error(buf) => {
+                if fire(state, i) {
+                    socket
+                        .send(axum::extract::ws::Message::Binary(buf.clone()))
+                        .await?;
+                    state.progress.lock().unwrap().position = i + 1;
+                    return socket.close().await;
+                }
+            }
+            crate::script::Event::Disconnect => {
+                if fire(state, i) {
+                    state.progress.lock().unwrap().position = i + 1;
+                    return socket.close().await;
+                }
+            }
+            crate::script::Event::Sleep(duration) => {
+                tokio::time::sleep(*duration).await;
+            }
+        }
+        i += 1;
+        state.progress.lock().unwrap().position = i;
+    }
+
+    // Stay connected, like a relay with nothing new to send, until the subscriber leaves.
+    while let Some(msg) = socket.recv().await {
+        if let axum::extract::ws::Message::Close(_) = msg? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn future_cursor_frame() -> Vec<u8> {
+    #[derive(serde::Serialize)]
+    struct Header {
+        op: i8,
+    }
+    #[derive(serde::Serialize)]
+    struct ErrorBody {
+        error: &'static str,
+        message: &'static str,
+    }
+
+    let mut buf = vec![];
+    ciborium::into_writer(&Header { op: -1 }, &mut buf).expect("encoding header failed");
+    ciborium::into_writer(
+        &ErrorBody {
+            error: "FutureCursor",
+            message: "Cursor in the future.",
+        },
+        &mut buf,
+    )
+    .expect("encoding error body failed");
+    buf
+}
@@ -0,0 +1,166 @@
+//! Runs the relay against a script and subscribes to it like the ingester does.
+
+use futures::StreamExt;
+
+/// The relay process, killed when the test ends.
+struct Relay {
+    child: std::process::Child,
+    addr: std::net::SocketAddr,
+    dir: std::path::PathBuf,
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Relay {
+    async fn start(name: &str, script: &[serde_json::Value]) -> Relay {
+        let dir = std::env::temp_dir().join(format!(
+            "skylight-testrelay-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("script.jsonl");
+        std::fs::write(
+            &path,
+            script
+                .iter()
+                .map(|step| format!("{step}\n"))
+                .collect::<String>(),
+        )
+        .unwrap();
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let relay = Relay {
+            child: std::process::Command::new(env!("CARGO_BIN_EXE_skylight-testrelay"))
+                .arg("--listen")
+                .arg(addr.to_string())
+                .arg("--script")
+                .arg(&path)
+                .spawn()
+                .unwrap(),
+            addr,
+            dir,
+        };
+
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(addr).await.is_ok() {
+                return relay;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("relay didn't start listening on {addr}");
+    }
+
+    /// Subscribes and collects the frames sent until the relay closes the connection or stops
+    /// sending, as pairs of the header and body.
+    async fn subscribe(
+        &self,
+        cursor: Option<i64>,
+    ) -> Vec<(ciborium::value::Value, ciborium::value::Value)> {
+        let mut url = format!("ws://{}/xrpc/com.atproto.sync.subscribeRepos", self.addr);
+        if let Some(cursor) = cursor {
+            url.push_str(&format!("?cursor={cursor}"));
+        }
+        let (mut stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        let mut frames = vec![];
+        while let Ok(Some(msg)) =
+            tokio::time::timeout(std::time::Duration::from_millis(500), stream.next()).await
+        {
+            match msg.unwrap() {
+                tokio_tungstenite::tungstenite::Message::Binary(buf) => {
+                    let mut cursor = std::io::Cursor::new(buf);
+                    let header = ciborium::from_reader(&mut cursor).unwrap();
+                    let body = ciborium::from_reader(&mut cursor).unwrap();
+                    frames.push((header, body));
+                }
+                tokio_tungstenite::tungstenite::Message::Close(_) => {
+                    break;
+                }
+                _ => {}
+            }
+        }
+        frames
+    }
+}
+
+fn field<'a>(value: &'a ciborium::value::Value, name: &str) -> Option<&'a ciborium::value::Value> {
+    value.as_map()?.iter().find_map(|(k, v)| {
+        if k.as_text() == Some(name) {
+            Some(v)
+        } else {
+            None
+        }
+    })
+}
+
+fn op(header: &ciborium::value::Value) -> i128 {
+    field(header, "op").unwrap().as_integer().unwrap().into()
+}
+
+fn seq(body: &ciborium::value::Value) -> i128 {
+    field(body, "seq").unwrap().as_integer().unwrap().into()
+}
+
+fn error(body: &ciborium::value::Value) -> &str {
+    field(body, "error").unwrap().as_text().unwrap()
+}
+
+fn commit(seq: i64) -> serde_json::Value {
+    serde_json::json!({
+        "message": {
+            "type": "#commit",
+            "body": {"seq": seq, "repo": "did:plc:alice", "rev": format!("rev{seq}")}
+        }
+    })
+}
+
+#[tokio::test]
+async fn error_frame_then_reconnect() {
+    let relay = Relay::start(
+        "error",
+        &[
+            commit(1),
+            serde_json::json!({"error": {"error": "ConsumerTooSlow", "message": "Stream consumer too slow"}}),
+            commit(2),
+        ],
+    )
+    .await;
+
+    let frames = relay.subscribe(None).await;
+    assert_eq!(frames.len(), 2);
+    assert_eq!(op(&frames[0].0), 1);
+    assert_eq!(seq(&frames[0].1), 1);
+    assert_eq!(op(&frames[1].0), -1);
+    assert_eq!(error(&frames[1].1), "ConsumerTooSlow");
+
+    // The error only happens once, so reconnecting from the cursor gets past it.
+    let frames = relay.subscribe(Some(1)).await;
+    assert_eq!(frames.len(), 1);
+    assert_eq!(op(&frames[0].0), 1);
+    assert_eq!(seq(&frames[0].1), 2);
+}
+
+#[tokio::test]
+async fn future_cursor() {
+    let relay = Relay::start("future", &[commit(1), commit(2)]).await;
+
+    let frames = relay.subscribe(Some(3)).await;
+    assert_eq!(frames.len(), 1);
+    assert_eq!(op(&frames[0].0), -1);
+    assert_eq!(error(&frames[0].1), "FutureCursor");
+
+    // Reconnecting from a cursor the relay has reached resumes after it.
+    let frames = relay.subscribe(Some(1)).await;
+    assert_eq!(frames.len(), 1);
+    assert_eq!(seq(&frames[0].1), 2);
+}